@@ -3,7 +3,7 @@ use gda_core::compute::tensor::{Tensor, TensorError};
 use gda_core::compute::processor::{Processor, ProcessorSelectionConstraint, Compiled};
 const VEC_SIZE: usize = 60000;
 
-fn benchmark_gpu<'a>(shader: &'a mut Compiled<'a, f32>, gpu: &mut Processor) {
+fn benchmark_gpu(shader: &Compiled<f32>, gpu: &mut Processor) {
     gpu.execute(shader);
 }
 
@@ -17,8 +17,6 @@ fn benchmark_cpu(a: &Vec<f32>, b: &Vec<f32>) {
 }
 
 fn criterion_benchmark(c: &mut Criterion) {
-    let result_tensor: Tensor<f32> = Tensor::zeros_from_shape(vec![VEC_SIZE]);   
-
     let mut a = Tensor::new(vec![1f32; VEC_SIZE]);
     let mut b = Tensor::new(vec![2f32; VEC_SIZE]);
 
@@ -26,14 +24,14 @@ fn criterion_benchmark(c: &mut Criterion) {
 
     let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
     
-    let mut shader = gpu.build(op, result_tensor);
+    let shader = gpu.build(op).unwrap();
     
 
     let a1 = vec![1f32; VEC_SIZE];
     let b1 = vec![2f32; VEC_SIZE];
     {
         c.bench_function("CPU", |b| b.iter(|| benchmark_cpu(black_box(&a1), black_box(&b1))));
-        c.bench_function("GPU", |b| b.iter(|| benchmark_gpu(&mut shader, &mut gpu)));
+        c.bench_function("GPU", |b| b.iter(|| benchmark_gpu(&shader, &mut gpu)));
     }
 }
 
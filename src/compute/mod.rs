@@ -2,7 +2,7 @@ pub mod processor;
 pub mod tensor;
 
 use tensor::{Tensor, TensorError};
-use processor::{Processor, ProcessorSelectionConstraint};
+use processor::{DoublePrecision, InferredCompiled, Processor, ProcessorSelectionConstraint};
 
 pub fn run() -> Vec<u32> {
     //build_shader();
@@ -29,19 +29,21 @@ pub fn build_shader() {
 #[cfg(test)]
 mod test {
     use super::*;
+    use tensor::autodiff::{grad, jvp};
+    use tensor::{DataType, Graph, Input, Operation, Quantization, QuantizedTensor, SerializedOperation, TensorNames, TensorOperationResult, TensorValue};
+    use processor::Compiled;
 
     #[test]
     pub fn test_add_1() -> Result<(), TensorError> {
         let a = Tensor::new(vec![1f32, 2.0, 3.0]);
         let b = Tensor::new(vec![1f32]);
 
-        let result_tensor: Tensor<f32> = Tensor::zeros_from_shape(vec![3]);
     
         let op = &a + &b;        
 
         let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
         
-        let shader = gpu.build(op, result_tensor);
+        let shader = gpu.build::<f32>(op)?;
         let res1 = gpu.execute(&shader);
 
         let v_result = res1?.get_value().to_vec();
@@ -58,13 +60,12 @@ mod test {
         let a = Tensor::new(vec![1f32, 2.0, 3.0]);
         let b = Tensor::new(vec![2f32]);
 
-        let result_tensor: Tensor<f32> = Tensor::zeros_from_shape(vec![3]);
     
         let op = &a + &b;        
 
         let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
         
-        let shader = gpu.build(op, result_tensor);
+        let shader = gpu.build::<f32>(op)?;
         let res1 = gpu.execute(&shader);
 
         let v_result = res1?.get_value().to_vec();
@@ -81,13 +82,12 @@ mod test {
         let a = Tensor::new(vec![1f32, 2.0, 3.0]);
         let b = Tensor::new(vec![1f32, 2.0, 3.0]);
 
-        let result_tensor: Tensor<f32> = Tensor::zeros_from_shape(vec![3]);
     
         let op = &a + &b;        
 
         let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
         
-        let shader = gpu.build(op, result_tensor);
+        let shader = gpu.build::<f32>(op)?;
         let res1 = gpu.execute(&shader);
 
         let v_result = res1?.get_value().to_vec();
@@ -105,13 +105,12 @@ mod test {
         let a = Tensor::new(vec![1f32, 2.0, 3.0]);
         let b = Tensor::new(vec![1f32]);
 
-        let result_tensor: Tensor<f32> = Tensor::zeros_from_shape(vec![3]);
     
         let op = &a - &b;        
 
         let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
         
-        let shader = gpu.build(op, result_tensor);
+        let shader = gpu.build::<f32>(op)?;
         let res1 = gpu.execute(&shader);
 
         let v_result = res1?.get_value().to_vec();
@@ -128,13 +127,12 @@ mod test {
         let a = Tensor::new(vec![1f32, 2.0, 3.0]);
         let b = Tensor::new(vec![2f32]);
 
-        let result_tensor: Tensor<f32> = Tensor::zeros_from_shape(vec![3]);
     
         let op = &a - &b;        
 
         let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
         
-        let shader = gpu.build(op, result_tensor);
+        let shader = gpu.build::<f32>(op)?;
         let res1 = gpu.execute(&shader);
 
         let v_result = res1?.get_value().to_vec();
@@ -151,13 +149,12 @@ mod test {
         let a = Tensor::new(vec![2f32, 4.0, 17.0]);
         let b = Tensor::new(vec![1f32, 2.0, 3.0]);
 
-        let result_tensor: Tensor<f32> = Tensor::zeros_from_shape(vec![3]);
     
         let op = &a - &b;        
 
         let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
         
-        let shader = gpu.build(op, result_tensor);
+        let shader = gpu.build::<f32>(op)?;
         let res1 = gpu.execute(&shader);
 
         let v_result = res1?.get_value().to_vec();
@@ -178,7 +175,6 @@ mod test {
         let d = Tensor::new(vec![1f32, 2.0, 3.0]);
         let e = Tensor::new(vec![1f32]);
 
-        let result_tensor: Tensor<f32> = Tensor::zeros_from_shape(vec![3]);
     
         let mut op = &a + &b - &c;      
         let op2 = &d - &e;
@@ -186,7 +182,7 @@ mod test {
 
         let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
         
-        let shader = gpu.build(op, result_tensor);
+        let shader = gpu.build::<f32>(op)?;
         let res1 = gpu.execute(&shader);
 
         let v_result = res1?.get_value().to_vec();
@@ -198,6 +194,871 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    pub fn test_add_double() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![1f64, 2.0, 3.0]);
+        let b = Tensor::new(vec![0.1f64]);
+
+    
+        let op = &a + &b;        
+
+        let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
+        assert_eq!(gpu.double_precision(), DoublePrecision::Host);
+        
+        let shader = gpu.build::<f64>(op)?;
+        let res1 = gpu.execute(&shader);
+
+        let v_result = res1?.get_value().to_vec();
+
+        assert_eq!(v_result[0], 1f64 + 0.1);
+        assert_eq!(v_result[1], 2f64 + 0.1);
+        assert_eq!(v_result[2], 3f64 + 0.1);
+
+        Ok(())
+    }
+    #[test]
+    pub fn test_quantize_roundtrip() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![-1f32, 0.0, 0.5, 1.0]);
+
+        let q: QuantizedTensor<i8> = QuantizedTensor::quantize(&a, Quantization::from_range::<i8>(-1.0, 1.0))?;
+        let v_result = q.dequantize().get_value().to_vec();
+
+        for (x, y) in v_result.iter().zip(a.get_value().iter()) {
+            assert!((x - y).abs() < 0.01);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_quantized_add() -> Result<(), TensorError> {
+        let a = Tensor::with_shape(vec![1f32, 2.0, 3.0, 4.0], vec![2, 2]);
+        let b = Tensor::with_shape(vec![0.5f32, 0.5, 1.0, 1.0], vec![2, 2]);
+
+        let per_row = Quantization::PerChannel {axis: 0, scales: vec![0.5, 0.25], zero_points: vec![0, 10]};
+        let qa: QuantizedTensor<u8> = QuantizedTensor::quantize(&a, per_row)?;
+        let qb: QuantizedTensor<u8> = QuantizedTensor::quantize(&b, Quantization::PerTensor {scale: 0.5, zero_point: 3})?;
+
+        let q = qa.add(&qb, Quantization::PerTensor {scale: 0.5, zero_point: 0})?;
+
+        assert_eq!(q.dequantize().get_value().to_vec(), vec![1.5f32, 2.5, 4.0, 5.0]);
+
+        // rescaled to a finer scale, saturating at the end of the range of u8
+        let q = qa.add(&qb, Quantization::PerTensor {scale: 1.0 / 64.0, zero_point: 0})?;
+        assert_eq!(q.get_value(), &[96u8, 160, 255, 255]);
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_quantize_saturates() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![1e30f32, -1e30]);
+
+        let q: QuantizedTensor<i8> = QuantizedTensor::quantize(&a, Quantization::PerTensor {scale: 1e-3, zero_point: 100})?;
+        assert_eq!(q.get_value().to_vec(), vec![127i8, -128]);
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_quantized_matmul() -> Result<(), TensorError> {
+        let a = Tensor::with_shape(vec![1f32, 2.0, 3.0, 4.0], vec![2, 2]);
+        let b = Tensor::with_shape(vec![1f32, 0.0, 0.0, 2.0], vec![2, 2]);
+
+        let qa: QuantizedTensor<i8> = QuantizedTensor::quantize(&a, Quantization::PerTensor {scale: 0.5, zero_point: 0})?;
+        let per_col = Quantization::PerChannel {axis: 1, scales: vec![1.0, 0.5], zero_points: vec![0, -2]};
+        let qb: QuantizedTensor<i8> = QuantizedTensor::quantize(&b, per_col)?;
+
+        let q = qa.matmul(&qb, Quantization::PerTensor {scale: 0.5, zero_point: 0})?;
+
+        assert_eq!(q.get_shape(), &vec![2, 2]);
+        assert_eq!(q.dequantize().get_value().to_vec(), vec![1f32, 4.0, 3.0, 8.0]);
+
+        let per_k = Quantization::PerChannel {axis: 0, scales: vec![1.0, 1.0], zero_points: vec![0, 0]};
+        let qb: QuantizedTensor<i8> = QuantizedTensor::quantize(&b, per_k)?;
+        assert!(qa.matmul(&qb, Quantization::PerTensor {scale: 0.5, zero_point: 0}).is_err());
+
+        Ok(())
+    }
+    #[test]
+    pub fn test_shared_subexpression() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0]);
+        let b = Tensor::new(vec![2f32]);
+
+
+        let x = &a + &b;
+        let op = x.clone() + x;
+
+        let graph = op.to_graph();
+        assert_eq!(graph.input_count(), 2);
+        assert_eq!(graph.nodes().len(), 4);
+        assert_eq!(graph.consumers(2), &[3]);
+
+        let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
+        
+        let shader = gpu.build::<f32>(op)?;
+        let res1 = gpu.execute(&shader);
+
+        let v_result = res1?.get_value().to_vec();
+
+        assert_eq!(v_result[0], 6f32);
+        assert_eq!(v_result[1], 8f32);
+        assert_eq!(v_result[2], 10f32);
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_binding_table() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0]);
+        let b = Tensor::new(vec![4f32, 5.0, 6.0]);
+        let c = Tensor::new(vec![1i32]);
+
+        let op = (&a + &b) - (&a - &c);
+
+        let bindings = op.to_graph().bindings();
+        assert_eq!(bindings.iter().map(|x| x.get_binding()).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        assert_eq!(bindings[2].get_data_type(), DataType::Int);
+        assert!(!bindings[2].is_result());
+        assert!(bindings[3].is_result());
+        assert_eq!(bindings[3].get_shape(), &vec![3]);
+        assert!(format!("{}", op).contains("binding 0: f32 [3]\n    binding 2: i32 [1]"));
+
+        let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
+        let shader = gpu.build::<f32>(op)?;
+        assert_eq!(shader.bindings(), bindings);
+        assert_eq!(gpu.execute(&shader)?.get_value().to_vec(), vec![5f32, 6.0, 7.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_graph_commutative_cse() {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0]);
+        let b = Tensor::new(vec![1f32, 2.0, 3.0]);
+
+        let op = (&a + &b) - (&b + &a);
+        let graph = op.to_graph();
+
+        assert_eq!(graph.input_count(), 2);
+        assert_eq!(graph.nodes().len(), 4);
+        assert_eq!(graph.result_bindings(), vec![2]);
+    }
+    #[test]
+    pub fn test_build_many() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0]);
+        let b = Tensor::new(vec![2f32]);
+
+        let ops = vec![&a + &b, &a - &b];
+
+        let graph = Graph::from_operations(&ops);
+        assert_eq!(graph.input_count(), 2);
+        assert_eq!(graph.outputs().len(), 2);
+        assert_eq!(graph.result_bindings(), vec![2, 3]);
+
+        let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
+        
+        let shader = gpu.build_many(&ops)?;
+        let results: Vec<Tensor<f32>> = gpu.execute_many(&shader)?;
+
+        assert_eq!(results[0].get_value().to_vec(), vec![3f32, 4.0, 5.0]);
+        assert_eq!(results[1].get_value().to_vec(), vec![-1f32, 0.0, 1.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_inner_error() {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0]);
+        let b = Tensor::new(vec![1f32, 2.0]);
+        let c = Tensor::new(vec![4f32, 5.0, 6.0]);
+        let d = Tensor::new(vec![2f32]);
+
+        // operations on a failed operation report its error, not one of their own
+        let error = |op: Operation| match op.get_result() {
+            TensorOperationResult::Error(TensorError::ShapeError(x)) => x,
+            x => panic!("{:?}", x)
+        };
+        assert_eq!(error((&a + &b) * &c), "Add shapes not matching");
+        assert_eq!(error((&a + &b) * &d), "Add shapes not matching");
+        assert_eq!(error(&c - (&a + &b)), "Add shapes not matching");
+        assert_eq!(error((&a + &b).transpose()), "Add shapes not matching");
+        assert_eq!(error((&a + &b).sum().sqrt()), "Add shapes not matching");
+    }
+
+    #[test]
+    pub fn test_operation_display() {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0]);
+        let b = Tensor::new(vec![1i32, 2]);
+        let c = Tensor::new(vec![1f32]);
+
+        let op = (&a + &c) - &b;
+
+        assert_eq!(format!("{}", op), "- -> ShapeError(\"Add shapes not matching\")\n  + -> f32 [3]\n    binding 0: f32 [3]\n    binding 1: f32 [1]\n  binding 2: i32 [2]\n");
+
+        let dot = op.to_dot();
+        assert!(dot.starts_with("digraph operation {"));
+        assert!(dot.contains("n0 -> n1 [label=\"left\"];"));
+        assert!(dot.contains("n0 -> n4 [label=\"right\"];"));
+        assert!(dot.contains("binding 2: i32 [2]"));
+    }
+
+    #[test]
+    pub fn test_serialize_tensor() {
+        let a = Tensor::with_shape(vec![1f32, 2.0, 3.0, 4.0], vec![2, 2]);
+
+        let json = serde_json::to_string(&a).unwrap();
+        assert_eq!(json, "{\"shape\":[2,2],\"data\":[1.0,2.0,3.0,4.0],\"is_const\":false}");
+
+        let b: Tensor<f32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(b.get_shape().to_vec(), vec![2, 2]);
+        assert_eq!(b.get_value().to_vec(), a.get_value().to_vec());
+
+        assert!(serde_json::from_str::<Tensor<f32>>("{\"shape\":[3],\"data\":[1.0]}").is_err());
+    }
+
+    #[test]
+    pub fn test_serialize_operation() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0]);
+        let b = Tensor::new(vec![2f32]);
+
+        let mut names = TensorNames::new();
+        names.insert("a", &a);
+
+        let x = &a + &b;
+        let op = x.clone() - x;
+        let json = serde_json::to_string(&SerializedOperation::from_operation(&op, &names)).unwrap();
+
+        let serialized: SerializedOperation = serde_json::from_str(&json).unwrap();
+        let c = Tensor::new(vec![4f32, 5.0, 6.0]);
+        let mut names = TensorNames::new();
+        names.insert("a", &c);
+
+        let loaded = serialized.to_operation(&names)?;
+        assert_eq!(loaded.get_shape(), vec![3]);
+
+        let graph = loaded.to_graph();
+        assert_eq!(graph.input_count(), 2);
+        assert_eq!(graph.nodes().len(), 4);
+        assert_eq!(graph.evaluate_host()[0][1], 0f64);
+
+        assert!(serialized.to_operation(&TensorNames::new()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_simplify_constants() {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0]);
+        let c1 = Tensor::constant(vec![2f32]);
+        let c2 = Tensor::constant(vec![3f32]);
+
+        let folded = (&c1 + &c2) + &a;
+        let graph = folded.to_graph().simplify(true);
+        assert_eq!(graph.input_count(), 1);
+        assert_eq!(graph.nodes().len(), 3);
+        assert_eq!(graph.evaluate_host()[0][1], 7f64);
+
+        let op = (&a + &c1) + &c2;
+        assert_eq!(op.to_graph().simplify(true).nodes().len(), 5);
+
+        let graph = op.to_graph().simplify(false);
+        assert_eq!(graph.nodes().len(), 3);
+        assert_eq!(graph.evaluate_host()[0][0], 6f64);
+    }
+
+    #[test]
+    pub fn test_simplify_constant_arrays() {
+        let a = Tensor::new(vec![1f32, 1.0, 1.0]);
+        let c1 = Tensor::constant(vec![1f32, 2.0, 3.0]);
+        let c2 = Tensor::constant(vec![4f32, 5.0, 6.0]);
+
+        let graph = (&c1 + &c2).to_graph().simplify(true);
+        assert_eq!(graph.input_count(), 1);
+        assert_eq!(graph.nodes().len(), 1);
+        assert_eq!(graph.evaluate_host()[0], vec![5f64, 7.0, 9.0]);
+
+        let graph = ((&c1 * &c2) + &a).to_graph().simplify(true);
+        assert_eq!(graph.input_count(), 2);
+        assert_eq!(graph.nodes().len(), 3);
+        assert_eq!(graph.evaluate_host()[0][2], 19f64);
+    }
+
+    #[test]
+    pub fn test_simplify_identities() {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0]);
+        let b = Tensor::new(vec![1i32, 2, 3]);
+        let zero = Tensor::constant(vec![0f32]);
+        let zero_int = Tensor::constant(vec![0i32]);
+
+        let op = &a - &zero;
+        let graph = op.to_graph().simplify(true);
+        assert_eq!(graph.nodes().len(), 1);
+        assert_eq!(graph.evaluate_host()[0][2], 3f64);
+
+        let op = &a + &zero;
+        assert_eq!(op.to_graph().simplify(true).nodes().len(), 3);
+        assert_eq!(op.to_graph().simplify(false).nodes().len(), 1);
+
+        let op = &b + &zero_int;
+        assert_eq!(op.to_graph().simplify(true).nodes().len(), 1);
+
+        let op = (&a + &a) - (&a + &a);
+        assert_eq!(op.to_graph().simplify(true).nodes().len(), 3);
+
+        let graph = op.to_graph().simplify(false);
+        assert_eq!(graph.input_count(), 0);
+        assert_eq!(graph.nodes().len(), 1);
+        assert_eq!(graph.evaluate_host()[0][0], 0f64);
+    }
+
+    #[test]
+    pub fn test_grad() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0]);
+        let b = Tensor::new(vec![4f32, 5.0, 6.0]);
+        let s = Tensor::new(vec![2f32]);
+
+        let loss = ((&a * &b) / &s).sum();
+        let grads = grad(&loss, &[&a, &b, &s])?;
+
+        let da: Vec<f64> = grads[0].to_graph().evaluate_host().remove(0);
+        let db: Vec<f64> = grads[1].to_graph().evaluate_host().remove(0);
+        assert_eq!(da, vec![2.0, 2.5, 3.0]);
+        assert_eq!(db, vec![0.5, 1.0, 1.5]);
+        assert_eq!(grads[2].get_shape(), vec![1]);
+        assert_eq!(grads[2].to_graph().evaluate_host()[0][0], -8.0);
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_grad_unary() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![1f32, 4.0]);
+        let b = Tensor::new(vec![1f32, 1.0]);
+
+        let grads = grad(&(Operation::from(&a).sqrt() - &a), &[&a, &b])?;
+        assert_eq!(grads[0].to_graph().simplify(true).evaluate_host()[0][1], -0.75);
+        assert_eq!(grads[1].get_shape(), vec![2]);
+        assert_eq!(grads[1].to_graph().evaluate_host()[0][0], 0.0);
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_grad_matmul() -> Result<(), TensorError> {
+        let a = Tensor::with_shape(vec![1f32, 2.0, 3.0, 4.0, 5.0, 6.0], vec![2, 3]);
+        let b = Tensor::with_shape(vec![1f32, 2.0, 3.0, 4.0, 5.0, 6.0], vec![3, 2]);
+
+        let product = Operation::from(&a).matmul(Operation::from(&b));
+        let graph = product.to_graph();
+        assert_eq!(graph.evaluate_host()[0], vec![22.0, 28.0, 49.0, 64.0]);
+
+        let grads = grad(&product.clone().sum(), &[&a, &b])?;
+        assert_eq!(grads[0].get_shape(), vec![2, 3]);
+        assert_eq!(grads[1].get_shape(), vec![3, 2]);
+        let da: Vec<f64> = grads[0].to_graph().evaluate_host().remove(0);
+        let db: Vec<f64> = grads[1].to_graph().evaluate_host().remove(0);
+        assert_eq!(da, vec![3.0, 7.0, 11.0, 3.0, 7.0, 11.0]);
+        assert_eq!(db, vec![5.0, 5.0, 7.0, 7.0, 9.0, 9.0]);
+
+        let (_, derivative) = jvp(&product, &[(&a, &a)])?;
+        assert_eq!(derivative.to_graph().evaluate_host()[0][3], 64.0);
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_matmul() -> Result<(), TensorError> {
+        let a = Tensor::with_shape(vec![1f32, 2.0, 3.0, 4.0, 5.0, 6.0], vec![2, 3]);
+        let b = Tensor::with_shape(vec![1f32, 2.0, 3.0, 4.0, 5.0, 6.0], vec![3, 2]);
+
+        let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
+        let shader = gpu.build::<f32>(Operation::from(&a).matmul(Operation::from(&b)))?;
+        let result = gpu.execute(&shader)?;
+        assert_eq!(*result.get_shape(), vec![2, 2]);
+        assert_eq!(result.get_value().to_vec(), vec![22f32, 28.0, 49.0, 64.0]);
+
+        // the transposed sum is stored by a first kernel and multiplied in a second one
+        let shader = gpu.build::<f32>((Operation::from(&b).transpose() + &a).matmul(Operation::from(&b)))?;
+        assert_eq!(gpu.execute(&shader)?.get_value().to_vec(), vec![57f32, 72.0, 93.0, 120.0]);
+
+        assert!(gpu.build::<f32>(Operation::from(&a).matmul(Operation::from(&a))).is_err());
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_jvp() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![1f32, 4.0, 9.0]);
+        let b = Tensor::new(vec![2f32, 2.0, 2.0]);
+        let da = Tensor::new(vec![1f32, 1.0, 0.0]);
+
+        let (output, derivative) = jvp(&(Operation::from(&a).sqrt() * &b), &[(&a, &da)])?;
+        let graph = Graph::from_operations(&[output, derivative]);
+
+        // the square root is computed once for the output and its derivative
+        assert_eq!(graph.nodes().iter().filter(|x| matches!(x, tensor::Node::SingleOp {..})).count(), 1);
+        assert_eq!(graph.evaluate_host().iter().map(|x| x[1]).collect::<Vec<_>>(), vec![4.0, 0.5]);
+        assert_eq!(graph.evaluate_host().iter().map(|x| x[2]).collect::<Vec<_>>(), vec![6.0, 0.0]);
+
+        let sum = (&a * &b).sum();
+        let (_, derivative) = jvp(&sum, &[(&a, &da)])?;
+        assert_eq!(derivative.to_graph().evaluate_host()[0][0], 4.0);
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_eval() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0]);
+        let b = Tensor::new(vec![2f32]);
+
+        assert!(matches!((&a + &b).eval::<i32>(), Err(TensorError::TypeError(_))));
+
+        let c: Tensor<f32> = (&a + &b).eval()?;
+        assert_eq!(c.get_value().to_vec(), vec![3f32, 4.0, 5.0]);
+
+        let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
+        let d: Tensor<f32> = (&a * &b).eval_on(&mut gpu)?;
+        let stats = gpu.shader_cache_stats();
+        let e: Tensor<f32> = (&a * &b).eval_on(&mut gpu)?;
+        assert_eq!(d.get_value().to_vec(), e.get_value().to_vec());
+
+        // the second evaluation reuses the pipelines without compiling or looking up a shader
+        assert_eq!(gpu.pipeline_cache_len(), 1);
+        assert_eq!(gpu.shader_cache_stats(), stats);
+        assert_eq!(d.get_value().to_vec(), vec![2f32, 4.0, 6.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_build_inferred() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![1i32, 2, 3]);
+        let b = Tensor::new(vec![2i32]);
+        let c = Tensor::new(vec![1i32, 2]);
+
+        let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
+        assert!(matches!(gpu.build::<f32>(&a * &b), Err(TensorError::TypeError(_))));
+        assert!(matches!(gpu.build_inferred(&a + &c), Err(TensorError::ShapeError(_))));
+
+        let shader = gpu.build_inferred(&a * &b)?;
+        assert!(matches!(shader, InferredCompiled::Int(_)));
+
+        match gpu.execute_inferred(&shader)? {
+            TensorValue::Int(x) => assert_eq!(x.get_value().to_vec(), vec![2, 4, 6]),
+            _ => panic!("result should be i32")
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_chained_kernels() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0]);
+        let b = Tensor::new(vec![1f32]);
+        let x: Tensor<f32> = Tensor::zeros_from_shape(vec![3]);
+
+        let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
+        let first = gpu.build::<f32>(&a + &b)?;
+        let second = gpu.build::<f32>(&x + &x)?;
+
+        gpu.feed(&second, &x, &first.device_result(0)?)?;
+
+        gpu.run(&first)?;
+        gpu.run(&second)?;
+        assert_eq!(gpu.read(&second)?.get_value().to_vec(), vec![4f32, 6.0, 8.0]);
+
+        a.set(&[0], 3f32);
+        gpu.run(&first)?;
+        gpu.run(&second)?;
+        assert_eq!(gpu.read(&second)?.get_value().to_vec(), vec![8f32, 6.0, 8.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_placeholder() -> Result<(), TensorError> {
+        let x = Input::new("x", DataType::Float, vec![3]);
+        let b = Tensor::new(vec![1f32]);
+
+        let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
+        let shader = gpu.build::<f32>(x.op() + &b)?;
+
+        let t1 = Tensor::new(vec![1f32, 2.0, 3.0]);
+        assert_eq!(gpu.execute_with(&shader, &[("x", (&t1).into())])?.get_value().to_vec(), vec![2f32, 3.0, 4.0]);
+
+        let t2 = Tensor::new(vec![5f32, 6.0, 7.0]);
+        assert_eq!(gpu.execute_with(&shader, &[("x", (&t2).into())])?.get_value().to_vec(), vec![6f32, 7.0, 8.0]);
+
+        // inputs of different element types are bound in one call
+        let n = Input::new("n", DataType::Int, vec![1]);
+        let mixed = gpu.build::<f32>(x.op() * n.op())?;
+        let scale = Tensor::new(vec![2i32]);
+        assert_eq!(gpu.execute_with(&mixed, &[("x", (&t1).into()), ("n", (&scale).into())])?.get_value().to_vec(), vec![2f32, 4.0, 6.0]);
+
+        assert!(matches!(shader.bind("x", &Tensor::new(vec![1f32, 2.0])), Err(TensorError::ShapeError(_))));
+        assert!(matches!(shader.bind("x", &Tensor::new(vec![1i32, 2, 3])), Err(TensorError::TypeError(_))));
+        assert!(shader.bind("y", &t1).is_err());
+
+        Ok(())
+    }
+
+    struct Model {
+        weight: Tensor<f32>,
+        forward: Compiled<f32>
+    }
+
+    fn build_model(gpu: &mut Processor) -> Result<Model, TensorError> {
+        let weight = Tensor::new(vec![1f32, 2.0, 3.0]);
+        let bias = Tensor::new(vec![1f32]);
+        let forward = gpu.build::<f32>(&weight + &bias)?;
+        Ok(Model {weight, forward})
+    }
+
+    #[test]
+    pub fn test_owned_compiled() -> Result<(), TensorError> {
+        let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
+        let model = build_model(&mut gpu)?;
+
+        assert_eq!(gpu.execute(&model.forward)?.get_value().to_vec(), vec![2f32, 3.0, 4.0]);
+        model.weight.change_value(vec![4f32, 5.0, 6.0])?;
+        assert_eq!(gpu.execute(&model.forward)?.get_value().to_vec(), vec![5f32, 6.0, 7.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_owned_operation() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![1f32, 2.0]);
+        let op = {
+            let b = Tensor::new(vec![3f32, 4.0]);
+            &a * &b
+        };
+
+        let graph = op.to_graph();
+        assert_eq!(graph.evaluate_host()[0][1], 8f64);
+        a.change_value(vec![2f32, 3.0])?;
+        assert_eq!(graph.evaluate_host()[0][1], 12f64);
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_memory_plan() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0]);
+        let b = Tensor::new(vec![2f32]);
+
+        let op = ((((&a * &b).sum() + &a).sum() + &a).sum() + &a);
+
+        let plan = op.to_graph().plan_memory();
+        // every sum runs in a kernel of its own
+        assert_eq!(plan.get_stage_count(), 7);
+        // the arrays share one buffer and the sums another, no kernel needs two of either
+        assert_eq!(plan.get_pool(), &[12, 4]);
+        // the padded input, the dimensions of every kernel and the scalar in the uniform buffer,
+        // the padded result with its staging buffer and the pool
+        assert_eq!(plan.get_peak_memory(), 16 + (7 * 16 + 16) + (16 + 12) + 16);
+
+        let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
+        gpu.set_memory_limit(Some(187));
+        assert!(matches!(gpu.build::<f32>(op.clone()), Err(TensorError::MemoryError(_))));
+
+        gpu.set_memory_limit(Some(188));
+        let shader = gpu.build::<f32>(op)?;
+        assert_eq!(shader.peak_memory(), 188);
+        assert_eq!(gpu.execute(&shader)?.get_value().to_vec(), vec![133f32, 134.0, 135.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_workgroup_count() {
+        assert_eq!(Graph::get_workgroup_count_for(3, 64), [1, 1, 1]);
+        assert_eq!(Graph::get_workgroup_count_for(65535 * 64, 64), [65535, 1, 1]);
+        // one more element than fits along x needs a second row of workgroups
+        assert_eq!(Graph::get_workgroup_count_for(65535 * 64 + 1, 64), [65535, 2, 1]);
+        assert_eq!(Graph::get_workgroup_count_for(65535 * 65535 + 1, 1), [65535, 65535, 2]);
+        assert_eq!(Graph::get_index_strides(65535 * 64 + 1, 64), (65535 * 64, 65535 * 64 * 2));
+    }
+
+    #[test]
+    pub fn test_workgroup_size() -> Result<(), TensorError> {
+        let a = Tensor::with_shape(vec![1f32, 2.0, 3.0, 4.0, 5.0, 6.0], vec![2, 3]);
+        let b = Tensor::new(vec![1f32]);
+        assert_eq!(a.shape_len(), 6);
+
+        let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
+        // the last workgroup has two invocations without an element
+        gpu.set_workgroup_size(Some(4));
+        let shader = gpu.build::<f32>(&a + &b)?;
+
+        assert_eq!(gpu.execute(&shader)?.get_value().to_vec(), vec![2f32, 3.0, 4.0, 5.0, 6.0, 7.0]);
+
+        gpu.set_workgroup_size(Some(1 << 20));
+        assert!(gpu.build::<f32>(&a + &b).is_err());
+
+        gpu.set_workgroup_size(None);
+        assert!(gpu.build::<f32>(&a + &b).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_sum_stage() {
+        let a = Tensor::new(vec![1f32; 1000]);
+        let b = Tensor::new(vec![2f32]);
+
+        let graph = (&a - (&a * &b).sum()).to_graph();
+        let plan = graph.plan_memory();
+
+        // the sum is computed once, instead of by every invocation of the subtraction
+        assert_eq!(plan.get_stage_count(), 3);
+        assert_eq!(graph.get_stage_invocations(&plan, 0), 1000);
+        assert_eq!(graph.get_stage_invocations(&plan, 1), 1);
+        assert_eq!(graph.get_workgroup_count(&plan, 1, 64), [1, 1, 1]);
+        assert_eq!(graph.get_stage_invocations(&plan, 2), 1000);
+    }
+
+    #[test]
+    pub fn test_uniform_layout() {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0]);
+        let b = Tensor::new(vec![2i32]);
+        let c = Tensor::new(vec![0.5f64]);
+
+        let graph = ((&a * &b).sum() + &c).to_graph();
+        let plan = graph.plan_memory();
+        let uniforms = graph.plan_uniforms(&plan, 64);
+
+        // the dimensions of all three kernels, then the scalars at their natural alignment
+        assert_eq!(uniforms.get_offset(0), None);
+        assert_eq!(uniforms.get_offset(1), Some(48));
+        assert_eq!(uniforms.get_offset(2), Some(56));
+        assert_eq!(uniforms.get_size(), 64);
+        assert_eq!(uniforms.get_binding(), graph.bindings().len() as u32 + plan.get_pool().len() as u32);
+    }
+
+    #[test]
+    pub fn test_vector_width() {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let b = Tensor::new(vec![2i32]);
+        let c = Tensor::new(vec![1f64, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let d = Tensor::new(vec![1f32, 2.0, 3.0]);
+
+        let width = |op: Operation| {
+            let graph = op.to_graph();
+            let plan = graph.plan_memory();
+            graph.get_vector_width(&plan, 0)
+        };
+        assert_eq!(width((&a * &b + &a).sqrt()), 4);
+        assert_eq!(width(&c + &c), 1);
+        assert_eq!(width(&d + &d), 1);
+        assert_eq!(width((&a * &b).sum() + &a), 1);
+    }
+
+    #[test]
+    pub fn test_vectorized() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let b = Tensor::new(vec![2i32]);
+
+        let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
+        let compiled = gpu.build::<f32>(&a * &b + &a)?;
+        assert_eq!(gpu.execute(&compiled)?.get_value().to_vec(), vec![3f32, 6.0, 9.0, 12.0, 15.0, 18.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_changed_range() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![0f32; 8]);
+        let start = a.get_change();
+
+        a.set(&[5], 1.0);
+        a.set(&[2], 1.0);
+        assert_eq!(a.get_changed_range(start), 2..6);
+        assert_eq!(a.get_changed_range(start + 1), 2..3);
+
+        a.change_value(vec![1f32; 8])?;
+        assert_eq!(a.get_changed_range(start + 2), 0..8);
+
+        for _ in 0..20 {
+            a.set(&[7], 2.0);
+        }
+        assert_eq!(a.get_changed_range(start + 3), 0..8);
+        assert_eq!(a.get_changed_range(start + 20), 7..8);
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_in_place_update() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0, 4.0, 5.0]);
+        let b = Tensor::new(vec![1f32, 1.0, 1.0, 1.0, 1.0]);
+
+        let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
+        let compiled = gpu.build::<f32>(&a + &b)?;
+        gpu.execute(&compiled)?;
+
+        a.set(&[3], 10.0);
+        b.change_value(vec![2f32; 5])?;
+        assert_eq!(gpu.execute(&compiled)?.get_value().to_vec(), vec![3f32, 4.0, 5.0, 12.0, 7.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_scalar_update() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0]);
+        let b = Tensor::new(vec![2f32]);
+
+        let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
+        let compiled = gpu.build::<f32>(&a * &b)?;
+        assert_eq!(gpu.execute(&compiled)?.get_value().to_vec(), vec![2f32, 4.0, 6.0]);
+
+        b.set(&[0], 3.0);
+        assert_eq!(gpu.execute(&compiled)?.get_value().to_vec(), vec![3f32, 6.0, 9.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_kernel_source() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0]);
+
+        let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
+        let compiled = gpu.build::<f32>(&a + &a)?;
+        let source = compiled.source()?;
+        let spirv = compiled.spirv()?;
+
+        assert_eq!(source.len(), spirv.len());
+        assert!(source[0].contains("main"));
+        assert_eq!(&spirv[0][..4], &0x0723_0203u32.to_le_bytes());
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_shader_cache() -> Result<(), TensorError> {
+        use processor::CacheStats;
+        use processor::gpu::cache::ShaderCache;
+
+        let directory = std::env::temp_dir().join(format!("gda_shader_cache_{}", std::process::id()));
+        let spirv = vec![0x03, 0x02, 0x23, 0x07, 1, 0, 0, 0];
+
+        let mut cache = ShaderCache::new(None);
+        cache.set_directory(Some(directory.clone()))?;
+        assert_eq!(cache.get_or_compile("a", |_| spirv.clone()), spirv);
+        assert_eq!(cache.get_or_compile("a", |_| panic!("compiled twice")), spirv);
+        assert_eq!(cache.get_stats(), CacheStats {hits: 1, misses: 1});
+
+        // a new process finds the module on disk
+        let mut cache = ShaderCache::new(Some(directory.clone()));
+        assert_eq!(cache.get_or_compile("a", |_| panic!("compiled twice")), spirv);
+        assert_eq!(cache.get_stats(), CacheStats {hits: 1, misses: 0});
+
+        cache.clear()?;
+        assert_eq!(cache.get_or_compile("a", |_| spirv.clone()), spirv);
+        assert_eq!(cache.get_stats(), CacheStats {hits: 1, misses: 1});
+
+        // a file of another source with the same key is compiled again
+        let path = std::fs::read_dir(&directory).unwrap().next().unwrap().unwrap().path();
+        let mut file = std::fs::read(&path).unwrap();
+        let last = file.len() - 9;
+        file[last] += 1;
+        std::fs::write(&path, file).unwrap();
+        let mut cache = ShaderCache::new(Some(directory.clone()));
+        assert_eq!(cache.get_or_compile("a", |_| spirv.clone()), spirv);
+        assert_eq!(cache.get_stats(), CacheStats {hits: 0, misses: 1});
+
+        cache.clear()?;
+        std::fs::remove_dir(&directory).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_structural_hash() {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0]);
+        let b = Tensor::new(vec![2f32]);
+        let c = Tensor::new(vec![4f32, 5.0, 6.0]);
+        let d = Tensor::new(vec![1f32, 2.0]);
+
+        let hash = (&a * &b + &a).to_graph().structural_hash();
+        assert_eq!((&c * &b + &c).to_graph().structural_hash(), hash);
+        assert_ne!((&d * &b + &d).to_graph().structural_hash(), hash);
+        assert_ne!((&a * &b - &a).to_graph().structural_hash(), hash);
+        assert_ne!((&a * &b + &c).to_graph().structural_hash(), hash);
+
+        let key = (&a * &b + &a).to_graph().structural_key();
+        assert!((&c * &b + &c).to_graph().structural_key() == key);
+        assert!((&d * &b + &d).to_graph().structural_key() != key);
+        assert!((&a * &b - &a).to_graph().structural_key() != key);
+        assert!((&b * &a + &a).to_graph().structural_key() != key);
+    }
+
+    #[test]
+    pub fn test_pipeline_cache() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0]);
+        let b = Tensor::new(vec![4f32, 5.0, 6.0]);
+        let c = Tensor::new(vec![1f32, 2.0]);
+
+        let mut gpu = Processor::new(ProcessorSelectionConstraint::None);
+        let first = gpu.build::<f32>(&a + &a)?;
+        let second = gpu.build::<f32>(&b + &b)?;
+        assert_eq!(gpu.pipeline_cache_len(), 1);
+
+        assert_eq!(gpu.execute(&first)?.get_value().to_vec(), vec![2f32, 4.0, 6.0]);
+        assert_eq!(gpu.execute(&second)?.get_value().to_vec(), vec![8f32, 10.0, 12.0]);
+
+        gpu.set_pipeline_cache_capacity(1);
+        gpu.build::<f32>(&c + &c)?;
+        assert_eq!(gpu.pipeline_cache_len(), 1);
+        // evicted pipelines stay with the kernels using them
+        assert_eq!(gpu.execute(&first)?.get_value().to_vec(), vec![2f32, 4.0, 6.0]);
+
+        Ok(())
+    }
+
+    #[cfg(all(feature = "wgsl", not(feature = "spirv")))]
+    #[test]
+    pub fn test_wgsl() -> Result<(), TensorError> {
+        let a = Tensor::new(vec![1f32, 2.0, 3.0]);
+        let b = Tensor::new(vec![2i32]);
+
+        let op = ((&a * &b).sum() + &a).sum().sqrt() + &b;
+        let graph = op.to_graph();
+        let plan = graph.plan_memory();
+
+        for stage in 0..plan.get_stage_count() {
+            // compiling validates the module and panics on invalid source
+            assert!(!Graph::build_shader(&graph.build_wgsl(&plan, stage, 64)?).is_empty());
+        }
+
+        let c = Tensor::new(vec![1f64, 2.0, 3.0]);
+        let graph = (&c + &c).to_graph();
+        assert!(matches!(graph.build_wgsl(&graph.plan_memory(), 0, 64), Err(TensorError::Unimplemented(_))));
+        Ok(())
+    }
+
+    #[cfg(feature = "spirv")]
+    #[test]
+    pub fn test_spirv() {
+        use rspirv::spirv::{Decoration, Op};
+
+        let a = Tensor::new(vec![1f32, 2.0, 3.0]);
+        let b = Tensor::new(vec![2i32]);
+
+        let op = ((&a * &b).sum() + &a).sum().sqrt() + &b;
+        let graph = op.to_graph();
+        let plan = graph.plan_memory();
+
+        // the array input and the uniform buffer, then the pool buffers and results each kernel
+        // reads or writes
+        let bindings = [3, 4, 4, 4, 4];
+        for stage in 0..plan.get_stage_count() {
+            let module = rspirv::dr::load_bytes(graph.build_spirv(&plan, stage, 64)).unwrap();
+
+            assert_eq!(module.entry_points.len(), 1);
+            let binding_count = module.annotations.iter()
+                .filter(|x| x.class.opcode == Op::Decorate && x.operands[1] == rspirv::dr::Operand::Decoration(Decoration::Binding))
+                .count();
+            assert_eq!(binding_count, bindings[stage]);
+        }
+    }
 
     #[test]
     pub fn test_tensor_indexing_1() {
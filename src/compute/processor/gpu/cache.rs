@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::compute::processor::CacheStats;
+use crate::compute::processor::gpu::shader::Pipelines;
+use crate::compute::tensor::TensorError;
+use crate::compute::tensor::graph::{StructuralKey, SHADER_COMPILER};
+
+/// First word of every SPIR-V module.
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+/// SPIR-V of compiled kernel sources, kept in memory and, with a directory set, on disk so
+/// that later processes don't compile them again.
+pub struct ShaderCache {
+    directory: Option<PathBuf>,
+    /// Source and SPIR-V by key, the source tells apart sources whose keys collide.
+    modules: HashMap<u64, (String, Vec<u8>)>,
+    stats: CacheStats
+}
+
+impl ShaderCache {
+    pub fn new(directory: Option<PathBuf>) -> ShaderCache {
+        ShaderCache {directory, modules: HashMap::new(), stats: CacheStats {hits: 0, misses: 0}}
+    }
+
+    /// Stores modules in `directory` from now on, creating it if needed. `None` keeps them in
+    /// memory only.
+    pub fn set_directory(&mut self, directory: Option<PathBuf>) -> Result<(), TensorError> {
+        if let Some(x) = &directory {
+            fs::create_dir_all(x).map_err(|e| TensorError::IoError(format!("Can't create shader cache {}: {}", x.display(), e)))?;
+        }
+        self.directory = directory;
+        Ok(())
+    }
+
+    /// SPIR-V of `source`, compiled with `compile` unless it is cached in memory or on disk.
+    /// Failing to write the cache file only costs the next process a compilation.
+    pub fn get_or_compile(&mut self, source: &str, compile: impl FnOnce(&str) -> Vec<u8>) -> Vec<u8> {
+        let key = Self::get_key(source);
+
+        if let Some((cached, spirv)) = self.modules.get(&key) {
+            if cached == source {
+                self.stats.hits += 1;
+                return spirv.clone()
+            }
+        }
+
+        if let Some(spirv) = self.read(key, source) {
+            self.stats.hits += 1;
+            self.modules.insert(key, (source.to_string(), spirv.clone()));
+            return spirv
+        }
+
+        self.stats.misses += 1;
+        let spirv = compile(source);
+        self.write(key, source, &spirv);
+        self.modules.insert(key, (source.to_string(), spirv.clone()));
+        spirv
+    }
+
+    pub fn get_stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Drops every cached module, including the files in the cache directory.
+    pub fn clear(&mut self) -> Result<(), TensorError> {
+        self.modules.clear();
+
+        if let Some(directory) = &self.directory {
+            let entries = fs::read_dir(directory).map_err(|e| TensorError::IoError(format!("Can't read shader cache {}: {}", directory.display(), e)))?;
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().is_some_and(|x| x == "spv") {
+                    fs::remove_file(&path).map_err(|e| TensorError::IoError(format!("Can't remove {}: {}", path.display(), e)))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// FNV-1a hash of the compiler and the source, which stays the same across builds of the
+    /// crate unlike the hasher of the standard library.
+    fn get_key(source: &str) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in SHADER_COMPILER.bytes().chain(Some(0)).chain(source.bytes()) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        hash
+    }
+
+    /// What a cache file starts with: the length of the compiler and source the key is
+    /// computed from, then both of them.
+    fn get_header(source: &str) -> Vec<u8> {
+        let length = (SHADER_COMPILER.len() + 1 + source.len()) as u64;
+        let text = SHADER_COMPILER.bytes().chain(Some(0)).chain(source.bytes());
+        length.to_le_bytes().iter().copied().chain(text).collect()
+    }
+
+    fn get_path(&self, key: u64) -> Option<PathBuf> {
+        self.directory.as_ref().map(|x| x.join(format!("{:016x}.spv", key)))
+    }
+
+    /// Cached module of `source`, ignoring files of other sources with the same key and files
+    /// that aren't SPIR-V.
+    fn read(&self, key: u64, source: &str) -> Option<Vec<u8>> {
+        let file = fs::read(self.get_path(key)?).ok()?;
+        let header = Self::get_header(source);
+        if !file.starts_with(&header) {
+            return None
+        }
+
+        let spirv = &file[header.len()..];
+        if spirv.len() % 4 != 0 || spirv.len() < 4 || u32::from_le_bytes([spirv[0], spirv[1], spirv[2], spirv[3]]) != SPIRV_MAGIC {
+            return None
+        }
+        Some(spirv.to_vec())
+    }
+
+    /// Writes through a temporary file, so that other processes never read half a module.
+    fn write(&self, key: u64, source: &str, spirv: &[u8]) {
+        if let Some(path) = self.get_path(key) {
+            let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+            let mut file = Self::get_header(source);
+            file.extend_from_slice(spirv);
+            if fs::write(&temporary, file).is_ok() && fs::rename(&temporary, &path).is_err() {
+                drop(fs::remove_file(&temporary));
+            }
+        }
+    }
+}
+
+/// Structural hash, workgroup size and structure of a graph. The hash and size are compared
+/// first, the structure tells apart graphs whose hashes collide.
+pub(crate) type PipelineKey = (u64, u32, StructuralKey);
+
+/// Pipelines of the graph structures built most recently, see `Processor::set_pipeline_cache_capacity`.
+pub(crate) struct PipelineCache {
+    /// Least recently used first.
+    entries: Vec<(PipelineKey, Rc<Pipelines>)>,
+    capacity: usize
+}
+
+impl PipelineCache {
+    pub(crate) fn new(capacity: usize) -> PipelineCache {
+        PipelineCache {entries: Vec::new(), capacity}
+    }
+
+    pub(crate) fn get(&mut self, key: &PipelineKey) -> Option<Rc<Pipelines>> {
+        let index = self.entries.iter().position(|(x, _)| x == key)?;
+        let entry = self.entries.remove(index);
+        let pipelines = entry.1.clone();
+        self.entries.push(entry);
+        Some(pipelines)
+    }
+
+    /// Adds `pipelines`, evicting the least recently used ones beyond the capacity. Kernels
+    /// built from evicted pipelines keep them.
+    pub(crate) fn insert(&mut self, key: PipelineKey, pipelines: Rc<Pipelines>) {
+        self.entries.push((key, pipelines));
+        self.evict();
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn evict(&mut self) {
+        let excess = self.entries.len().saturating_sub(self.capacity);
+        self.entries.drain(..excess);
+    }
+}
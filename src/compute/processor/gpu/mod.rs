@@ -2,30 +2,149 @@ use wgpu::{Device, Queue};
 use wgpu;
 
 use std::cell::RefCell;
+use std::path::PathBuf;
 use std::rc::Rc;
 
+pub mod cache;
 pub mod shader;
-use shader::Shader;
-use crate::compute::tensor::{Operation, SupportedDataTypes, Tensor, TensorOperationResult};
-use crate::compute::processor::Compiled;
+use cache::{PipelineCache, ShaderCache};
+use shader::{Pipelines, Shader};
+use crate::compute::tensor::{Graph, MemoryPlan, Operation, Shape, SupportedDataTypes, Tensor, TensorError, TensorHolder, TensorOperationResult};
+use crate::compute::processor::{CacheStats, Compiled, DoublePrecision};
+use crate::compute::processor::host::HostKernel;
+
+/// Graph structures whose pipelines are kept by default.
+const PIPELINE_CACHE_CAPACITY: usize = 64;
+/// Invocations per workgroup every Vulkan device supports, wgpu 0.6 doesn't report the real limit.
+const MAX_WORKGROUP_SIZE: u32 = 128;
 
 pub struct GPU {
     pub(crate) exist: bool,
     pub(crate) device: Option<Device>,
-    pub(crate) queue: Option<Queue>
+    pub(crate) queue: Option<Queue>,
+    pub(crate) double_precision: DoublePrecision,
+    pub(crate) strict_math: bool,
+    /// Device memory in bytes a single kernel may use, see `Processor::set_memory_limit`.
+    pub(crate) memory_limit: Option<u64>,
+    /// Invocations per workgroup of the kernels built next, see `Processor::set_workgroup_size`.
+    pub(crate) workgroup_size: u32,
+    /// Workgroup size picked for the device.
+    pub(crate) device_workgroup_size: u32,
+    /// SPIR-V of every shader compiled so far, keyed by a hash of its source.
+    pub(crate) shader_cache: ShaderCache,
+    pub(crate) pipeline_cache: PipelineCache
 }
 
 impl GPU {
-    pub(crate) fn build<'a, T>(&mut self, op: Operation<'a>, tensor: Tensor<T>) -> Compiled<'a, T> 
+    pub(crate) fn build<T>(&mut self, ops: &[Operation], tensors: Vec<Tensor<T>>) -> Result<Compiled<T>, TensorError> 
+    where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+        if self.workgroup_size > MAX_WORKGROUP_SIZE {
+            return Err(TensorError::Unimplemented(format!("Workgroup size {} exceeds the device limit of {}", self.workgroup_size, MAX_WORKGROUP_SIZE)))
+        }
+        if self.runs_on_host(ops) {
+            return Ok(Compiled::Host(Rc::new(RefCell::new(HostKernel::build(ops, tensors, self.strict_math)?))))
+        }
+        Ok(Compiled::GPU(Rc::new(RefCell::new(Shader::build(ops, self, tensors)?))))
+    }
+
+    pub(crate) fn dispatch<T>(&mut self, compiled: &Compiled<T>) -> Result<(), TensorError> 
+    where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+        match compiled {
+            Compiled::GPU(c) => (*c.borrow_mut()).dispatch(self),
+            Compiled::Host(c) => (*c.borrow_mut()).dispatch()
+        }
+    }
+
+    pub(crate) fn read_back<T>(&mut self, compiled: &Compiled<T>) -> Result<Vec<TensorOperationResult>, TensorError> 
     where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
-        Compiled::GPU(Rc::new(RefCell::new(Shader::build(op, self, tensor))))
+        match compiled {
+            Compiled::GPU(c) => (*c.borrow_mut()).read_back(self),
+            Compiled::Host(c) => Ok((*c.borrow()).read_back())
+        }
     }
 
-    pub(crate) fn execute<'a, T>(&mut self, compiled: &Compiled<'a, T>) -> TensorOperationResult 
+    pub(crate) fn link_input<T>(&mut self, compiled: &Compiled<T>, input: &TensorHolder, buffer: Rc<wgpu::Buffer>, shape: &Shape) -> Result<(), TensorError> 
     where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
-        return match compiled {
-            Compiled::GPU(c) => (*c.borrow_mut()).execute(self)
+        match compiled {
+            Compiled::GPU(c) => (*c.borrow_mut()).link_input(self, input, buffer, shape),
+            Compiled::Host(_) => Err(TensorError::Unimplemented("Device results can only be fed into GPU kernels".to_string()))
+        }
+    }
+
+    /// Whether `ops` are evaluated on the host, because they use `f64` the device can't compute.
+    fn runs_on_host(&self, ops: &[Operation]) -> bool {
+        // WGSL has no 64-bit floats
+        ops.iter().any(|op| op.uses_double()) && (self.double_precision == DoublePrecision::Host || cfg!(feature = "wgsl"))
+    }
+
+    /// Pipelines for `graph`, reused from an earlier graph of the same structure if possible.
+    pub(crate) fn get_pipelines(&mut self, graph: &Graph, plan: &MemoryPlan) -> Result<Rc<Pipelines>, TensorError> {
+        let key = (graph.structural_hash(), self.workgroup_size, graph.structural_key());
+        if let Some(x) = self.pipeline_cache.get(&key) {
+            return Ok(x)
+        }
+
+        let (mut sources, mut spirv) = (Vec::new(), Vec::new());
+        for stage in 0..plan.get_stage_count() {
+            let (source, module) = self.compile_stage(graph, plan, stage)?;
+            log::debug!("Compiled kernel {} of {} ({} bytes of SPIR-V):\n{}", stage + 1, plan.get_stage_count(), module.len(), source);
+            sources.push(source);
+            spirv.push(module);
+        }
+        let pipelines = Rc::new(Pipelines::build(self.device.as_ref().expect("No GPU!"), graph, plan, sources, spirv, self.workgroup_size));
+        self.pipeline_cache.insert(key, pipelines.clone());
+        Ok(pipelines)
+    }
+
+    /// Disassembly and SPIR-V of kernel `stage` of `graph`.
+    #[cfg(feature = "spirv")]
+    pub(crate) fn compile_stage(&mut self, graph: &Graph, plan: &MemoryPlan, stage: usize) -> Result<(String, Vec<u8>), TensorError> {
+        use rspirv::binary::Disassemble;
+
+        let spirv = graph.build_spirv(plan, stage, self.workgroup_size);
+        let source = rspirv::dr::load_bytes(&spirv).map(|x| x.disassemble()).unwrap_or_default();
+        Ok((source, spirv))
+    }
+
+    /// Source and SPIR-V of kernel `stage` of `graph`, compiled from `Graph::build_source`.
+    #[cfg(not(feature = "spirv"))]
+    pub(crate) fn compile_stage(&mut self, graph: &Graph, plan: &MemoryPlan, stage: usize) -> Result<(String, Vec<u8>), TensorError> {
+        let source = graph.build_source(plan, stage, self.workgroup_size)?;
+        let spirv = self.compile_source(&source);
+        Ok((source, spirv))
+    }
+
+    /// Compiles a kernel source, reusing the SPIR-V of a source compiled before.
+    #[cfg(not(feature = "spirv"))]
+    fn compile_source(&mut self, source: &str) -> Vec<u8> {
+        self.shader_cache.get_or_compile(source, Graph::build_shader)
+    }
+
+    /// Compiles the kernels `build` would for `ops` into the shader cache, without creating
+    /// any pipelines or buffers. SPIR-V kernels aren't compiled, so there is nothing to cache.
+    pub(crate) fn prewarm(&mut self, ops: &[Operation]) -> Result<(), TensorError> {
+        if cfg!(feature = "spirv") || self.runs_on_host(ops) {
+            return Ok(())
+        }
+
+        let graph = Graph::from_operations(ops).simplify(self.strict_math);
+        let plan = graph.plan_memory();
+        for stage in 0..plan.get_stage_count() {
+            self.compile_stage(&graph, &plan, stage)?;
         }
+        Ok(())
+    }
+
+    pub(crate) fn set_shader_cache_dir(&mut self, directory: Option<PathBuf>) -> Result<(), TensorError> {
+        self.shader_cache.set_directory(directory)
+    }
+
+    pub(crate) fn shader_cache_stats(&self) -> CacheStats {
+        self.shader_cache.get_stats()
+    }
+
+    pub(crate) fn clear_shader_cache(&mut self) -> Result<(), TensorError> {
+        self.shader_cache.clear()
     }
 }
 
@@ -41,6 +160,8 @@ impl GPU {
 
         //println!("{:?}", adapter.limits());
 
+        let workgroup_size = Self::detect_workgroup_size(&adapter);
+
         if let Ok((device, queue)) = 
             pollster::block_on(adapter
                 .request_device(
@@ -54,14 +175,41 @@ impl GPU {
             return GPU {
                 exist: true,
                 device: Some(device),
-                queue: Some(queue)
+                queue: Some(queue),
+                // wgpu 0.6 has no `SHADER_FLOAT64` feature to request, so `double` shaders
+                // would fail validation on every device and f64 graphs always run on the host
+                double_precision: DoublePrecision::Host,
+                strict_math: true,
+                memory_limit: None,
+                workgroup_size,
+                device_workgroup_size: workgroup_size,
+                shader_cache: ShaderCache::new(None),
+                pipeline_cache: PipelineCache::new(PIPELINE_CACHE_CAPACITY)
             }
         }
 
         GPU {
             exist: false,
             device: None,
-            queue: None
+            queue: None,
+            double_precision: DoublePrecision::Host,
+            strict_math: true,
+            memory_limit: None,
+            workgroup_size,
+            device_workgroup_size: workgroup_size,
+            shader_cache: ShaderCache::new(None),
+            pipeline_cache: PipelineCache::new(PIPELINE_CACHE_CAPACITY)
+        }
+    }
+
+    /// wgpu 0.6 doesn't report workgroup limits, so the size follows the SIMD width of
+    /// the vendor and stays below `MAX_WORKGROUP_SIZE`.
+    fn detect_workgroup_size(adapter: &wgpu::Adapter) -> u32 {
+        match adapter.get_info().vendor {
+            // NVIDIA warps and Intel EUs
+            0x10DE | 0x8086 => 32,
+            // AMD wavefronts and everything else
+            _ => 64
         }
     }
 }
\ No newline at end of file
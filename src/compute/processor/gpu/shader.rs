@@ -1,284 +1,398 @@
 use std::{convert::TryInto};
+use std::rc::Rc;
 use wgpu::util::DeviceExt;
 use wgpu::{Buffer, ComputePipeline, BindGroup, BindGroupLayout, Device};
 use wgpu;
-use crate::compute::tensor::{Operation, SupportedDataTypes, Tensor, TensorBinding, TensorError, TensorOperationResult, TensorHolder};
+use crate::compute::tensor::{BindingInfo, Graph, MemoryPlan, Operation, Shape, SupportedDataTypes, Tensor, TensorBinding, TensorError, TensorOperationResult, TensorHolder, UniformLayout};
+use crate::compute::tensor::vectorize::get_padded_size;
 
-pub struct Shader<'a, T> {
-    spirv: Vec<u8>,
-    result_binding: u32,
-    inputs: Vec<TensorBinding<'a>>,
-    compute_pipeline: ComputePipeline,
+/// What kernels built from graphs of the same structure share, see `Graph::structural_hash`.
+pub(crate) struct Pipelines {
+    /// Generated source of every kernel, the disassembly with the spirv feature.
+    pub(crate) sources: Vec<String>,
+    /// One module per kernel the graph is split into, run in order.
+    pub(crate) spirv: Vec<Vec<u8>>,
+    pub(crate) compute_pipelines: Vec<ComputePipeline>,
+    /// Workgroups every kernel is dispatched with.
+    pub(crate) workgroup_counts: Vec<[u32; 3]>,
+    pub(crate) uniforms: UniformLayout,
+    pub(crate) bind_group_layout: BindGroupLayout
+}
+
+pub struct Shader<T> {
+    pipelines: Rc<Pipelines>,
+    result_bindings: Vec<u32>,
+    bindings: Vec<BindingInfo>,
+    inputs: Vec<TensorBinding>,
     bind_group: BindGroup,
-    bind_group_layout: BindGroupLayout,
-    staging_buffer: Buffer,
-    storage_buffers: Vec<Buffer>,
-    result_buffer: Buffer,
-    result_size: wgpu::BufferAddress,
-    pub(crate) result_tensor: Tensor<T>,
-    tensor_result: TensorOperationResult
+    staging_buffers: Vec<Buffer>,
+    /// Buffer of every input, `None` for scalars unless they are linked.
+    storage_buffers: Vec<Option<Rc<Buffer>>>,
+    /// Dimensions of the kernels and the scalar inputs, see `Graph::plan_uniforms`.
+    uniform_buffer: Buffer,
+    /// Inputs reading the result buffer of another kernel instead of their tensor.
+    linked: Vec<bool>,
+    result_buffers: Vec<Rc<Buffer>>,
+    result_sizes: Vec<wgpu::BufferAddress>,
+    /// Buffers intermediates are passed between kernels in.
+    pool_bindings: Vec<u32>,
+    pool_buffers: Vec<Buffer>,
+    peak_memory: u64,
+    pub(crate) result_tensors: Vec<Tensor<T>>,
+    tensor_results: Vec<TensorOperationResult>
 }
 
-impl<'a, T> Shader<'a, T> 
-where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
-    pub(crate) fn build(op: Operation<'a>, gpu: &mut super::GPU, result_tensor: Tensor<T>) -> Shader<'a, T> { 
-        if let Some(device) = gpu.device.as_mut() {            
-            let (spirv, inputs, result_binding) = op.build_gpu(); 
+/// Buffers of a kernel in the order of their bindings, see `Graph::bindings`.
+struct BindGroupBuffers<'a> {
+    inputs: &'a [TensorBinding],
+    storage_buffers: &'a [Option<Rc<Buffer>>],
+    uniform_buffer: &'a Buffer,
+    result_bindings: &'a [u32],
+    result_buffers: &'a [Rc<Buffer>],
+    pool_bindings: &'a [u32],
+    pool_buffers: &'a [Buffer]
+}
 
-            inputs.iter().for_each(|x| drop(x.has_changed()));
+impl Pipelines {
+    /// Creates the pipelines of every kernel of `plan` from their SPIR-V.
+    pub(crate) fn build(device: &Device, graph: &Graph, plan: &MemoryPlan, sources: Vec<String>, spirv: Vec<Vec<u8>>, workgroup_size: u32) -> Pipelines {
+        let uniforms = graph.plan_uniforms(plan, workgroup_size);
+        let input_bindings = graph.inputs.iter().filter(|x| !x.value.is_single()).map(|x| x.id);
+        let pool_bindings = (0..plan.get_pool().len()).map(|x| graph.pool_binding(x));
 
-            let cs_module = (*device).create_shader_module(wgpu::util::make_spirv(&spirv));
+        let mut layout: Vec<wgpu::BindGroupLayoutEntry> = input_bindings.chain(graph.result_bindings()).chain(pool_bindings).map(|binding| { 
+            wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStage::COMPUTE,
+                ty: wgpu::BindingType::StorageBuffer {
+                    dynamic: false,
+                    readonly: false,
+                    min_binding_size: wgpu::BufferSize::new(3),
+                },
+                count: None,
+            }
+        }).collect();
 
-            let tensor_result = match op {
-                Operation::DualOp {result, ..} | Operation::SingleOp {result, ..} => result.copy(),
-                _ => panic!("unimplemented error case")
-            };
+        layout.push(wgpu::BindGroupLayoutEntry {
+            binding: uniforms.get_binding(),
+            visibility: wgpu::ShaderStage::COMPUTE,
+            ty: wgpu::BindingType::UniformBuffer {
+                dynamic: false,
+                min_binding_size: wgpu::BufferSize::new(uniforms.get_size()),
+            },
+            count: None,
+        });
 
-            if let TensorOperationResult::Error(x) = tensor_result {
-                panic!("unimplemented error case")
-            }
+        let bind_group_layout = (*device).create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &layout,
+        });
 
-            println!("Result vec form: {:?}", tensor_result);
+        let pipeline_layout = (*device).create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
 
-            let size = tensor_result.get_mem_size();
+        let compute_pipelines: Vec<ComputePipeline> = spirv.iter().map(|spirv| {
+            let cs_module = (*device).create_shader_module(wgpu::util::make_spirv(spirv));
 
-            let staging_buffer = (*device).create_buffer(&wgpu::BufferDescriptor {
+            (*device).create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
                 label: None,
-                size,
-                usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
-                mapped_at_creation: false,
-            });
+                layout: Some(&pipeline_layout),
+                compute_stage: wgpu::ProgrammableStageDescriptor {
+                    module: &cs_module,
+                    entry_point: "main",
+                },
+            })
+        }).collect();
 
-            let storage_buffers: Vec<wgpu::Buffer> = inputs.iter().map(|i| {
-                Self::get_buffer(device, &i.value, "Storage Buffer")
-            }).collect();            
+        let workgroup_counts = (0..plan.get_stage_count()).map(|stage| graph.get_workgroup_count(plan, stage, workgroup_size)).collect();
 
-            let result_buffer = Self::get_buffer_from_opres(device, &tensor_result, "Result Buffer");
+        Pipelines {sources, spirv, compute_pipelines, workgroup_counts, uniforms, bind_group_layout}
+    }
+}
 
-            let mut layout: Vec<wgpu::BindGroupLayoutEntry> = inputs.iter().map(|i| { 
-                wgpu::BindGroupLayoutEntry {
-                    binding: i.id,
-                    visibility: wgpu::ShaderStage::COMPUTE,
-                    ty: wgpu::BindingType::StorageBuffer {
-                        dynamic: false,
-                        readonly: false,
-                        min_binding_size: wgpu::BufferSize::new(3),
-                    },
-                    count: None,
-                }
-            }).collect();
+impl BindGroupBuffers<'_> {
+    fn create_bind_group(&self, device: &Device, pipelines: &Pipelines) -> BindGroup {
+        let mut b_group = Vec::<wgpu::BindGroupEntry>::new();
 
-            layout.push(wgpu::BindGroupLayoutEntry {
-                binding: result_binding,
-                visibility: wgpu::ShaderStage::COMPUTE,
-                ty: wgpu::BindingType::StorageBuffer {
-                    dynamic: false,
-                    readonly: false,
-                    min_binding_size: wgpu::BufferSize::new(3),
-                },
-                count: None,
+        for c in 0..self.inputs.len() {
+            // scalars are read from the uniform buffer, linked ones are copied into it before every dispatch
+            if self.inputs[c].value.is_single() {continue}
+            b_group.push(wgpu::BindGroupEntry {
+                binding: self.inputs[c].id,
+                resource: wgpu::BindingResource::Buffer(self.storage_buffers[c].as_ref().unwrap().slice(..)),
             });
+        }
 
-            let bind_group_layout = (*device).create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: None,
-                entries: &layout,
+        b_group.push(wgpu::BindGroupEntry {
+            binding: pipelines.uniforms.get_binding(),
+            resource: wgpu::BindingResource::Buffer(self.uniform_buffer.slice(..)),
+        });
+
+        for c in 0..self.result_bindings.len() {
+            b_group.push(wgpu::BindGroupEntry {
+                binding: self.result_bindings[c],
+                resource: wgpu::BindingResource::Buffer(self.result_buffers[c].slice(..)),
             });
+        }
 
-            let mut b_group = Vec::<wgpu::BindGroupEntry>::new();
+        for c in 0..self.pool_bindings.len() {
+            b_group.push(wgpu::BindGroupEntry {
+                binding: self.pool_bindings[c],
+                resource: wgpu::BindingResource::Buffer(self.pool_buffers[c].slice(..)),
+            });
+        }
 
-            for c in 0..inputs.len() {
-                b_group.push(wgpu::BindGroupEntry {
-                    binding: inputs[c].id,
-                    resource: wgpu::BindingResource::Buffer(storage_buffers[c].slice(..)),
-                });
+        (*device).create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &pipelines.bind_group_layout,
+            entries: &b_group,
+        })
+    }
+}
+
+impl<T> Shader<T> 
+where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+    pub(crate) fn build(ops: &[Operation], gpu: &mut super::GPU, result_tensors: Vec<Tensor<T>>) -> Result<Shader<T>, TensorError> { 
+        let graph = Graph::from_operations(ops).simplify(gpu.strict_math);
+        let plan = graph.plan_memory();
+        let peak_memory = plan.get_peak_memory();
+
+        if let Some(limit) = gpu.memory_limit {
+            if peak_memory > limit {
+                return Err(TensorError::MemoryError(format!("Kernel needs {} bytes of device memory, but the limit is {}", peak_memory, limit)))
             }
+        }
 
-        
-            b_group.push(wgpu::BindGroupEntry {
-                binding: result_binding,
-                resource: wgpu::BindingResource::Buffer(result_buffer.slice(..)),
-            });
+        let pipelines = gpu.get_pipelines(&graph, &plan)?;
 
-            let bind_group = (*device).create_bind_group(&wgpu::BindGroupDescriptor {
-                label: None,
-                layout: &bind_group_layout,
-                entries: &b_group,
-            });
+        if let Some(device) = gpu.device.as_mut() {            
+            let inputs: Vec<TensorBinding> = graph.inputs.iter().map(|x| x.copy()).collect();
+            let result_bindings = graph.result_bindings();
+            let bindings = graph.bindings();
 
-            
-            let pipeline_layout = (*device).create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: None,
-                bind_group_layouts: &[&bind_group_layout],
-                push_constant_ranges: &[],
-            });
+            inputs.iter().for_each(|x| x.mark_seen());
 
-            let compute_pipeline = (*device).create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                label: None,
-                layout: Some(&pipeline_layout),
-                compute_stage: wgpu::ProgrammableStageDescriptor {
-                    module: &cs_module,
-                    entry_point: "main",
-                },
+
+            let tensor_results: Vec<TensorOperationResult> = ops.iter().map(|op| op.get_result()).collect();
+
+            if let Some(TensorOperationResult::Error(x)) = tensor_results.iter().find(|x| matches!(x, TensorOperationResult::Error(_))) {
+                return Err(x.clone())
+            }
+
+            log::debug!("Kernel results: {:?}", tensor_results);
+
+            let result_sizes: Vec<wgpu::BufferAddress> = tensor_results.iter().map(|x| x.get_mem_size()).collect();
+
+            let staging_buffers: Vec<wgpu::Buffer> = result_sizes.iter().map(|size| {
+                (*device).create_buffer(&wgpu::BufferDescriptor {
+                    label: None,
+                    size: *size,
+                    usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            }).collect();
+
+            let storage_buffers: Vec<Option<Rc<wgpu::Buffer>>> = inputs.iter().map(|i| {
+                if i.value.is_single() {None} else {Some(Rc::new(Self::get_buffer(device, &i.value, "Storage Buffer")))}
+            }).collect();
+
+            let uniform_buffer = (*device).create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Uniform Buffer"),
+                contents: &pipelines.uniforms.get_contents(&inputs),
+                usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
             });
-            
-            return Shader {spirv, result_binding, inputs, staging_buffer, compute_pipeline, storage_buffers, bind_group, bind_group_layout, result_buffer, result_size: size, tensor_result, result_tensor}
+
+            let result_buffers: Vec<Rc<wgpu::Buffer>> = tensor_results.iter().map(|x| {
+                Ok(Rc::new(Self::get_buffer_from_opres(device, x, "Result Buffer")?))
+            }).collect::<Result<_, TensorError>>()?;
+
+            let pool_bindings: Vec<u32> = (0..plan.get_pool().len()).map(|x| graph.pool_binding(x)).collect();
+            let pool_buffers: Vec<wgpu::Buffer> = plan.get_pool().iter().map(|size| {
+                (*device).create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Pool Buffer"),
+                    size: *size,
+                    usage: wgpu::BufferUsage::STORAGE,
+                    mapped_at_creation: false,
+                })
+            }).collect();
+
+            let linked = vec![false; inputs.len()];
+
+            let bind_group = BindGroupBuffers {
+                inputs: &inputs, storage_buffers: &storage_buffers, uniform_buffer: &uniform_buffer, result_bindings: &result_bindings,
+                result_buffers: &result_buffers, pool_bindings: &pool_bindings, pool_buffers: &pool_buffers
+            }.create_bind_group(device, &pipelines);
+
+            return Ok(Shader {pipelines, result_bindings, bindings, inputs, staging_buffers, storage_buffers, uniform_buffer, linked, bind_group, result_buffers, result_sizes, pool_bindings, pool_buffers, peak_memory, tensor_results, result_tensors})
         }
         panic!("No GPU!");
     }
 
-    fn get_buffer_from_opres(device: &mut Device, tensor: &TensorOperationResult, label: &str) -> wgpu::Buffer {
-        return match tensor {
-            TensorOperationResult::Int(x) => {
-                (*device).create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Storage Buffer"),
-                    contents: bytemuck::cast_slice(&x.get_value()),
-                    usage: wgpu::BufferUsage::STORAGE
-                        | wgpu::BufferUsage::COPY_DST
-                        | wgpu::BufferUsage::COPY_SRC,
-                })              
-            },
-            TensorOperationResult::UInt(x) => {
-                (*device).create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Storage Buffer"),
-                    contents: bytemuck::cast_slice(&x.get_value()),
-                    usage: wgpu::BufferUsage::STORAGE
-                        | wgpu::BufferUsage::COPY_DST
-                        | wgpu::BufferUsage::COPY_SRC,
-                })              
-            },
-            TensorOperationResult::Float(x) => {
-                (*device).create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Storage Buffer"),
-                    contents: bytemuck::cast_slice(&x.get_value()),
-                    usage: wgpu::BufferUsage::STORAGE
-                        | wgpu::BufferUsage::COPY_DST
-                        | wgpu::BufferUsage::COPY_SRC,
-                })              
-            },
-            TensorOperationResult::Double(x) => {
-                (*device).create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Storage Buffer"),
-                    contents: bytemuck::cast_slice(&x.get_value()),
-                    usage: wgpu::BufferUsage::STORAGE
-                        | wgpu::BufferUsage::COPY_DST
-                        | wgpu::BufferUsage::COPY_SRC,
-                })              
-            },
-            _ => panic!("Unimlemented Error handling")
-        }
+    fn get_buffer_from_opres(device: &mut Device, tensor: &TensorOperationResult, label: &str) -> Result<wgpu::Buffer, TensorError> {
+        Ok(Self::create_storage_buffer(device, &tensor.get_bytes()?, label))
     }
 
     fn get_buffer(device: &mut Device, tensor: &TensorHolder, label: &str) -> wgpu::Buffer {
-        return match tensor {
-            TensorHolder::Int(x) => {
-                (*device).create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Storage Buffer"),
-                    contents: bytemuck::cast_slice(&x.get_value()),
-                    usage: wgpu::BufferUsage::STORAGE
-                        | wgpu::BufferUsage::COPY_DST
-                        | wgpu::BufferUsage::COPY_SRC,
-                })              
-            },
-            TensorHolder::UInt(x) => {
-                (*device).create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Storage Buffer"),
-                    contents: bytemuck::cast_slice(&x.get_value()),
-                    usage: wgpu::BufferUsage::STORAGE
-                        | wgpu::BufferUsage::COPY_DST
-                        | wgpu::BufferUsage::COPY_SRC,
-                })              
-            },
-            TensorHolder::Float(x) => {
-                (*device).create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Storage Buffer"),
-                    contents: bytemuck::cast_slice(&x.get_value()),
-                    usage: wgpu::BufferUsage::STORAGE
-                        | wgpu::BufferUsage::COPY_DST
-                        | wgpu::BufferUsage::COPY_SRC,
-                })              
-            },
-            TensorHolder::Double(x) => {
-                (*device).create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Storage Buffer"),
-                    contents: bytemuck::cast_slice(&x.get_value()),
-                    usage: wgpu::BufferUsage::STORAGE
-                        | wgpu::BufferUsage::COPY_DST
-                        | wgpu::BufferUsage::COPY_SRC,
-                })              
-            },
-            _ => panic!("Unimlemented Error handling")
+        Self::create_storage_buffer(device, &tensor.get_bytes(), label)
+    }
+
+    /// Storage buffer with `contents`, padded to a whole vector so that vectorized kernels
+    /// never read past its end.
+    fn create_storage_buffer(device: &mut Device, contents: &[u8], label: &str) -> wgpu::Buffer {
+        let mut contents = contents.to_vec();
+        contents.resize(get_padded_size(contents.len() as u64) as usize, 0);
+
+        (*device).create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents: &contents,
+            usage: wgpu::BufferUsage::STORAGE
+                | wgpu::BufferUsage::COPY_DST
+                | wgpu::BufferUsage::COPY_SRC,
+        })
+    }
+
+    pub(crate) fn peak_memory(&self) -> u64 {
+        self.peak_memory
+    }
+
+    pub(crate) fn bindings(&self) -> Vec<BindingInfo> {
+        self.bindings.clone()
+    }
+
+    pub(crate) fn sources(&self) -> Vec<String> {
+        self.pipelines.sources.clone()
+    }
+
+    pub(crate) fn spirv(&self) -> Vec<Vec<u8>> {
+        self.pipelines.spirv.clone()
+    }
+
+    /// The tensor behind the `Input` called `name`.
+    pub(crate) fn get_input(&self, name: &str) -> Option<TensorHolder> {
+        self.inputs.iter().find(|x| x.name.as_deref() == Some(name)).map(|x| x.value.copy())
+    }
+
+    /// The buffer result `index` is written to, which stays valid as long as any handle to it.
+    pub(crate) fn get_result_buffer(&self, index: usize) -> Option<(Rc<Buffer>, Shape)> {
+        let buffer = self.result_buffers.get(index)?;
+        Some((buffer.clone(), self.tensor_results[index].get_own_shape()))
+    }
+
+    /// Binds `buffer` in place of the tensor `input`, which is no longer uploaded afterwards.
+    pub(crate) fn link_input(&mut self, gpu: &mut super::GPU, input: &TensorHolder, buffer: Rc<Buffer>, shape: &Shape) -> Result<(), TensorError> {
+        let index = match self.inputs.iter().position(|x| x.value == *input) {
+            Some(x) => x,
+            None => return Err(TensorError::Unimplemented("Tensor is not an input of this kernel".to_string()))
+        };
+
+        if input.get_shape() != *shape {
+            return Err(TensorError::ShapeError(format!("Result of shape {:?} can't replace an input of shape {:?}", shape, input.get_shape())))
         }
+
+        if let Some(device) = gpu.device.as_mut() {
+            self.storage_buffers[index] = Some(buffer);
+            self.linked[index] = true;
+            self.bind_group = BindGroupBuffers {
+                inputs: &self.inputs, storage_buffers: &self.storage_buffers, uniform_buffer: &self.uniform_buffer, result_bindings: &self.result_bindings,
+                result_buffers: &self.result_buffers, pool_bindings: &self.pool_bindings, pool_buffers: &self.pool_buffers
+            }.create_bind_group(device, &self.pipelines);
+            return Ok(())
+        }
+        Err(TensorError::Unimplemented("failed to run compute on gpu!".to_owned()))
     }
 
-    pub(crate) fn execute(&mut self, gpu: &mut super::GPU) -> TensorOperationResult {
+    /// Uploads changed inputs and runs the kernel, leaving the results on the device.
+    pub(crate) fn dispatch(&mut self, gpu: &mut super::GPU) -> Result<(), TensorError> {
         if let Some(device) = gpu.device.as_mut() {            
-            let mut changed = false;
+            let queue = &mut gpu.queue.as_mut().unwrap();
+            // changed elements are written into the buffers in place, so the bind group stays valid
             for i in 0..self.inputs.len() {
-                if self.inputs[i].has_changed() {
-                    changed = true;
-                    self.storage_buffers[i] = Self::get_buffer(device, &self.inputs[i].value, "Storage Buffer");
-                }
-            }
-
-            if changed {
-                let mut b_group = Vec::<wgpu::BindGroupEntry>::new();
+                if self.linked[i] {continue}
+                let input = &self.inputs[i];
+                let range = match input.take_changed_range() {
+                    Some(x) => x,
+                    None => continue
+                };
 
-                for c in 0..self.inputs.len() {
-                    b_group.push(wgpu::BindGroupEntry {
-                        binding: self.inputs[c].id,
-                        resource: wgpu::BindingResource::Buffer(self.storage_buffers[c].slice(..)),
-                    });
+                match (self.pipelines.uniforms.get_offset(input.id), &self.storage_buffers[i]) {
+                    (Some(offset), _) => (*queue).write_buffer(&self.uniform_buffer, offset, &input.value.get_bytes()),
+                    (None, Some(buffer)) => {
+                        let offset = range.start as u64 * input.value.get_element_size();
+                        (*queue).write_buffer(buffer, offset, &input.value.get_range_bytes(range));
+                    },
+                    (None, None) => return Err(TensorError::Unimplemented(format!("Input {} has no buffer", input.id)))
                 }
-
-                //TODO: result_buffers shape shoud be implemented as relationship to other
-                b_group.push(wgpu::BindGroupEntry {
-                    binding: self.result_binding,
-                    resource: wgpu::BindingResource::Buffer(self.result_buffer.slice(..)),
-                });
-
-                self.bind_group = (*device).create_bind_group(&wgpu::BindGroupDescriptor {
-                    label: None,
-                    layout: &self.bind_group_layout,
-                    entries: &b_group,
-                });
             }
 
-            let queue = &mut gpu.queue.as_mut().unwrap();
             let mut encoder =
                 (*device).create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-            {
+            for i in 0..self.inputs.len() {
+                if let (true, Some(offset)) = (self.linked[i], self.pipelines.uniforms.get_offset(self.inputs[i].id)) {
+                    let buffer = self.storage_buffers[i].as_ref().unwrap();
+                    encoder.copy_buffer_to_buffer(buffer, 0, &self.uniform_buffer, offset, self.inputs[i].value.get_mem_size());
+                }
+            }
+            // a pass per kernel, so that each one sees the intermediates of the ones before
+            for (pipeline, [x, y, z]) in self.pipelines.compute_pipelines.iter().zip(&self.pipelines.workgroup_counts) {
                 let mut cpass = encoder.begin_compute_pass();
-                cpass.set_pipeline(&self.compute_pipeline);
+                cpass.set_pipeline(pipeline);
                 cpass.set_bind_group(0, &self.bind_group, &[]);
                 cpass.insert_debug_marker("compute collatz iterations");
-                cpass.dispatch(self.result_size as u32, 1, 1);
+                cpass.dispatch(*x, *y, *z);
+            }
+        
+            (*queue).submit(Some(encoder.finish()));
+            return Ok(())
+        }
+        Err(TensorError::Unimplemented("failed to run compute on gpu!".to_owned()))
+    }
+
+    /// Copies the results of the last dispatch back to the host.
+    pub(crate) fn read_back(&mut self, gpu: &mut super::GPU) -> Result<Vec<TensorOperationResult>, TensorError> {
+        if let Some(device) = gpu.device.as_mut() {            
+            let queue = &mut gpu.queue.as_mut().unwrap();
+            let mut encoder =
+                (*device).create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            for c in 0..self.result_buffers.len() {
+                encoder.copy_buffer_to_buffer(&self.result_buffers[c], 0, &self.staging_buffers[c], 0, self.result_sizes[c]);
             }
-            encoder.copy_buffer_to_buffer(&self.result_buffer, 0, &self.staging_buffer, 0, self.result_size);
         
             (*queue).submit(Some(encoder.finish()));
         
             // Note that we're not calling `.await` here.
-            let buffer_slice = self.staging_buffer.slice(..);
-            let buffer_future = buffer_slice.map_async(wgpu::MapMode::Read);
+            let buffer_futures: Vec<_> = self.staging_buffers.iter()
+                .map(|x| x.slice(..).map_async(wgpu::MapMode::Read))
+                .collect();
         
             // Poll the device in a blocking manner so that our future resolves.
             // In an actual application, `device.poll(...)` should
             // be called in an event loop or on another thread.
             (*device).poll(wgpu::Maintain::Wait);
+
+            let mut results = Vec::<TensorOperationResult>::with_capacity(buffer_futures.len());
         
-            if let Ok(()) = pollster::block_on(buffer_future) {
-                let data = buffer_slice.get_mapped_range();
-                self.tensor_result.map_from_staging_buffer(data);
+            for (c, buffer_future) in buffer_futures.into_iter().enumerate() {
+                if pollster::block_on(buffer_future).is_err() {
+                    return Err(TensorError::Unimplemented("failed to run compute on gpu!".to_owned()))
+                }
+
+                {
+                    let data = self.staging_buffers[c].slice(..).get_mapped_range();
+                    self.tensor_results[c].map_from_staging_buffer(data);
+                }
             
                 // With the current interface, we have to make sure all mapped views are
                 // dropped before we unmap the buffer.
-                self.staging_buffer.unmap();
+                self.staging_buffers[c].unmap();
             
-                return self.tensor_result.copy()
-            } else {
-                return TensorOperationResult::Error(TensorError::Unimplemented("failed to run compute on gpu!".to_owned()))
+                results.push(self.tensor_results[c].copy());
             }
+
+            return Ok(results)
         }
-        return TensorOperationResult::Error(TensorError::Unimplemented("failed to run compute on gpu!".to_owned()))
+        Err(TensorError::Unimplemented("failed to run compute on gpu!".to_owned()))
     }
 }
\ No newline at end of file
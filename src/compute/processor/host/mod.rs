@@ -0,0 +1,41 @@
+use crate::compute::tensor::{BindingInfo, Graph, Operation, SupportedDataTypes, Tensor, TensorError, TensorHolder, TensorOperationResult};
+
+pub struct HostKernel<T> {
+    graph: Graph,
+    pub(crate) result_tensors: Vec<Tensor<T>>,
+    tensor_results: Vec<TensorOperationResult>
+}
+
+impl<T> HostKernel<T> 
+where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+    pub(crate) fn build(ops: &[Operation], result_tensors: Vec<Tensor<T>>, strict: bool) -> Result<HostKernel<T>, TensorError> {
+        let tensor_results: Vec<TensorOperationResult> = ops.iter().map(|op| op.get_result()).collect();
+
+        if let Some(TensorOperationResult::Error(x)) = tensor_results.iter().find(|x| matches!(x, TensorOperationResult::Error(_))) {
+            return Err(x.clone())
+        }
+
+        Ok(HostKernel {graph: Graph::from_operations(ops).simplify(strict), result_tensors, tensor_results})
+    }
+
+    /// Evaluates the graph into the results without handing them out.
+    pub(crate) fn dispatch(&mut self) -> Result<(), TensorError> {
+        for (result, values) in self.tensor_results.iter().zip(self.graph.evaluate_host()) {
+            result.map_from_f64(values)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn bindings(&self) -> Vec<BindingInfo> {
+        self.graph.bindings()
+    }
+
+    /// The tensor behind the `Input` called `name`.
+    pub(crate) fn get_input(&self, name: &str) -> Option<TensorHolder> {
+        self.graph.inputs.iter().find(|x| x.name.as_deref() == Some(name)).map(|x| x.value.copy())
+    }
+
+    pub(crate) fn read_back(&self) -> Vec<TensorOperationResult> {
+        self.tensor_results.iter().map(|x| x.copy()).collect()
+    }
+}
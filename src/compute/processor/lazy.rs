@@ -0,0 +1,42 @@
+use std::cell::RefCell;
+
+use crate::compute::tensor::{Operation, SupportedDataTypes, Tensor, TensorError};
+use super::{Processor, ProcessorSelectionConstraint};
+
+thread_local! {
+    /// Processor used by `Operation::eval`, created on first use.
+    static DEFAULT_PROCESSOR: RefCell<Option<Processor>> = RefCell::new(None);
+}
+
+impl Operation {
+    /// Builds and runs this operation on the default processor of the current thread.
+    /// The result shape follows from the graph, `T` has to match its element type.
+    ///
+    /// The pipelines of the kernels are cached by the processor, so evaluating the same
+    /// expression again skips compiling them, but every buffer and the bind group are created
+    /// again. Hot loops should build once with `Processor::build` and `execute` repeatedly.
+    pub fn eval<T>(&self) -> Result<Tensor<T>, TensorError>
+    where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+        Processor::check_results::<T>(std::slice::from_ref(self))?;
+
+        DEFAULT_PROCESSOR.with(|processor| {
+            let mut processor = processor.borrow_mut();
+            let processor = processor.get_or_insert_with(|| Processor::new(ProcessorSelectionConstraint::None));
+            self.run_on(processor)
+        })
+    }
+
+    /// Like `eval`, but on `processor`.
+    pub fn eval_on<T>(&self, processor: &mut Processor) -> Result<Tensor<T>, TensorError>
+    where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+        Processor::check_results::<T>(std::slice::from_ref(self))?;
+        self.run_on(processor)
+    }
+
+    /// Builds and runs this operation, once `check_results` accepted it for `T`.
+    fn run_on<T>(&self, processor: &mut Processor) -> Result<Tensor<T>, TensorError>
+    where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+        let compiled = processor.build_checked::<T>(std::slice::from_ref(self))?;
+        processor.execute(&compiled)
+    }
+}
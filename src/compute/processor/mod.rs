@@ -1,13 +1,129 @@
 pub mod gpu;
+pub mod host;
+pub mod lazy;
 use gpu::GPU;
 use gpu::shader::Shader;
-use crate::compute::tensor::{Operation, SupportedDataTypes, Tensor, TensorError, TensorOperationResult};
+use host::HostKernel;
+use crate::compute::tensor::autodiff::jvp;
+use crate::compute::tensor::{BindingInfo, Operation, Shape, SupportedDataTypes, Tensor, TensorError, TensorHolder, TensorOperationResult, TensorValue};
 use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::path::PathBuf;
 use std::rc::Rc;
 
-pub enum Compiled<'a, T> 
+pub enum Compiled<T> 
 where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
-    GPU(Rc<RefCell<Shader<'a, T>>>)
+    GPU(Rc<RefCell<Shader<T>>>),
+    Host(Rc<RefCell<HostKernel<T>>>)
+}
+
+impl<T> Compiled<T>
+where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+    /// Handle to the device buffer result `index` is written to by `Processor::run`.
+    pub fn device_result(&self, index: usize) -> Result<DeviceResult<T>, TensorError> {
+        let result = match self {
+            Compiled::GPU(x) => x.borrow().get_result_buffer(index),
+            Compiled::Host(_) => return Err(TensorError::Unimplemented("Host kernels keep their results on the host".to_string()))
+        };
+
+        match result {
+            Some((buffer, shape)) => Ok(DeviceResult {buffer, shape, element: PhantomData}),
+            None => Err(TensorError::Unimplemented(format!("Kernel has no result {}", index)))
+        }
+    }
+
+    /// Device memory in bytes held by the kernel, with its intermediates planned by
+    /// `Graph::plan_memory`. Kernels evaluated on the host don't use any.
+    pub fn peak_memory(&self) -> u64 {
+        match self {
+            Compiled::GPU(x) => x.borrow().peak_memory(),
+            Compiled::Host(_) => 0
+        }
+    }
+
+    /// The binding table the kernel was built with, one entry per distinct tensor and result.
+    pub fn bindings(&self) -> Vec<BindingInfo> {
+        match self {
+            Compiled::GPU(x) => x.borrow().bindings(),
+            Compiled::Host(x) => x.borrow().bindings()
+        }
+    }
+
+    /// Generated source of every kernel the graph is split into, in the order they run. With
+    /// the spirv feature there is no source and this is the disassembly of the modules.
+    pub fn source(&self) -> Result<Vec<String>, TensorError> {
+        match self {
+            Compiled::GPU(x) => Ok(x.borrow().sources()),
+            Compiled::Host(_) => Err(TensorError::Unimplemented("Host kernels have no shader source".to_string()))
+        }
+    }
+
+    /// SPIR-V module of every kernel, in the order they run.
+    pub fn spirv(&self) -> Result<Vec<Vec<u8>>, TensorError> {
+        match self {
+            Compiled::GPU(x) => Ok(x.borrow().spirv()),
+            Compiled::Host(_) => Err(TensorError::Unimplemented("Host kernels have no SPIR-V".to_string()))
+        }
+    }
+
+    /// Binds `tensor` to the `Input` called `name` for the following runs. The values are
+    /// copied, so `tensor` doesn't have to outlive the kernel, but type and shape have to match.
+    pub fn bind<U>(&self, name: &str, tensor: &Tensor<U>) -> Result<(), TensorError>
+    where U: SupportedDataTypes + SupportedDataTypes<BindingType = U> {
+        self.bind_holder(name, &U::to_data_holder(tensor))
+    }
+
+    /// `bind` for a tensor of any element type.
+    pub fn bind_holder(&self, name: &str, tensor: &TensorHolder) -> Result<(), TensorError> {
+        let input = match self {
+            Compiled::GPU(x) => x.borrow().get_input(name),
+            Compiled::Host(x) => x.borrow().get_input(name)
+        };
+
+        match input {
+            Some(input) => input.assign_from(tensor),
+            None => Err(TensorError::Unimplemented(format!("No input named {}", name)))
+        }
+    }
+}
+
+/// A result that stays on the device, see `Processor::feed`.
+pub struct DeviceResult<T> {
+    buffer: Rc<wgpu::Buffer>,
+    shape: Shape,
+    element: PhantomData<T>
+}
+
+impl<T> DeviceResult<T> {
+    pub fn get_shape(&self) -> &Shape {
+        &self.shape
+    }
+}
+
+/// A kernel built with the element type its operation produces.
+pub enum InferredCompiled {
+    Int(Compiled<i32>),
+    UInt(Compiled<u32>),
+    Float(Compiled<f32>),
+    Double(Compiled<f64>)
+}
+
+/// How a processor runs graphs that contain `f64` tensors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DoublePrecision {
+    /// The device executes `double` shaders directly.
+    Native,
+    /// The graph is evaluated on the host in full `f64` precision.
+    Host
+}
+
+/// Lookups of the shader cache, see `Processor::set_shader_cache_dir`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheStats {
+    /// Kernels whose SPIR-V was found in memory or on disk.
+    pub hits: u64,
+    /// Kernels that had to be compiled.
+    pub misses: u64
 }
 
 pub enum AbstractProcessor {
@@ -15,16 +131,88 @@ pub enum AbstractProcessor {
 }
 
 impl AbstractProcessor {
-    fn build<'a, T>(&mut self, op: Operation<'a>, result: Tensor<T>) -> Compiled<'a, T>
+    fn build<T>(&mut self, ops: &[Operation], results: Vec<Tensor<T>>) -> Result<Compiled<T>, TensorError>
+    where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+        match self {
+            AbstractProcessor::GPU(x) => x.build(ops, results)
+        }
+    }
+    fn dispatch<T>(&mut self, compiled: &Compiled<T>) -> Result<(), TensorError>
+    where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+        match self {
+            AbstractProcessor::GPU(x) => x.dispatch(compiled)
+        }
+    }
+    fn read_back<T>(&mut self, compiled: &Compiled<T>) -> Result<Vec<TensorOperationResult>, TensorError>
     where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
         match self {
-            AbstractProcessor::GPU(x) => x.build(op, result)
+            AbstractProcessor::GPU(x) => x.read_back(compiled)
         }
     }
-    fn execute<'a, T>(&mut self, compiled: &Compiled<'a, T>) -> TensorOperationResult
+    fn link_input<T>(&mut self, compiled: &Compiled<T>, input: &TensorHolder, buffer: Rc<wgpu::Buffer>, shape: &Shape) -> Result<(), TensorError>
     where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
         match self {
-            AbstractProcessor::GPU(x) => x.execute(compiled)
+            AbstractProcessor::GPU(x) => x.link_input(compiled, input, buffer, shape)
+        }
+    }
+    fn double_precision(&self) -> DoublePrecision {
+        match self {
+            AbstractProcessor::GPU(x) => x.double_precision
+        }
+    }
+    fn set_strict_math(&mut self, strict: bool) {
+        match self {
+            AbstractProcessor::GPU(x) => x.strict_math = strict
+        }
+    }
+    fn set_memory_limit(&mut self, limit: Option<u64>) {
+        match self {
+            AbstractProcessor::GPU(x) => x.memory_limit = limit
+        }
+    }
+    fn prewarm(&mut self, ops: &[Operation]) -> Result<(), TensorError> {
+        match self {
+            AbstractProcessor::GPU(x) => x.prewarm(ops)
+        }
+    }
+    fn set_shader_cache_dir(&mut self, directory: Option<PathBuf>) -> Result<(), TensorError> {
+        match self {
+            AbstractProcessor::GPU(x) => x.set_shader_cache_dir(directory)
+        }
+    }
+    fn shader_cache_stats(&self) -> CacheStats {
+        match self {
+            AbstractProcessor::GPU(x) => x.shader_cache_stats()
+        }
+    }
+    fn clear_shader_cache(&mut self) -> Result<(), TensorError> {
+        match self {
+            AbstractProcessor::GPU(x) => x.clear_shader_cache()
+        }
+    }
+    fn pipeline_cache_len(&self) -> usize {
+        match self {
+            AbstractProcessor::GPU(x) => x.pipeline_cache.len()
+        }
+    }
+    fn set_pipeline_cache_capacity(&mut self, capacity: usize) {
+        match self {
+            AbstractProcessor::GPU(x) => x.pipeline_cache.set_capacity(capacity)
+        }
+    }
+    fn clear_pipeline_cache(&mut self) {
+        match self {
+            AbstractProcessor::GPU(x) => x.pipeline_cache.clear()
+        }
+    }
+    fn workgroup_size(&self) -> u32 {
+        match self {
+            AbstractProcessor::GPU(x) => x.workgroup_size
+        }
+    }
+    fn set_workgroup_size(&mut self, size: Option<u32>) {
+        match self {
+            AbstractProcessor::GPU(x) => x.workgroup_size = size.unwrap_or(x.device_workgroup_size).max(1)
         }
     }
 }
@@ -49,25 +237,206 @@ impl Processor {
         unimplemented!("Missing");
     }
 
-    pub fn build<'a, T>(&mut self,  op: Operation<'a>, result: Tensor<T>) -> Compiled<'a, T> 
+    /// Reports how graphs with `f64` tensors are executed by this processor. wgpu 0.6 can't
+    /// enable 64-bit floats in shaders, so GPUs always report `Host`.
+    pub fn double_precision(&self) -> DoublePrecision {
+        self.processor.double_precision()
+    }
+
+    /// With strict math (the default) graphs are only simplified where the result stays
+    /// bit-identical, disabling it also folds `x * 0`, `x - x` and reassociates constants.
+    pub fn set_strict_math(&mut self, strict: bool) {
+        self.processor.set_strict_math(strict)
+    }
+
+    /// Makes building fail with `TensorError::MemoryError` for kernels that would need more
+    /// than `limit` bytes of device memory, see `Compiled::peak_memory`. `None` removes the limit.
+    pub fn set_memory_limit(&mut self, limit: Option<u64>) {
+        self.processor.set_memory_limit(limit)
+    }
+
+    /// Invocations per workgroup of the kernels built by this processor.
+    pub fn workgroup_size(&self) -> u32 {
+        self.processor.workgroup_size()
+    }
+
+    /// Overrides the workgroup size picked for the device for kernels built afterwards,
+    /// `None` restores it. Sizes above the limit of the device make building fail.
+    pub fn set_workgroup_size(&mut self, size: Option<u32>) {
+        self.processor.set_workgroup_size(size)
+    }
+
+    /// Keeps the SPIR-V of compiled kernels in `directory`, so that building the same kernel
+    /// in a later process skips the compiler. `None` only caches them in memory.
+    ///
+    /// With the `spirv` feature kernels are emitted as SPIR-V without a compiler, so the
+    /// shader cache stays empty and `shader_cache_stats` and `prewarm_shader_cache` do nothing.
+    pub fn set_shader_cache_dir(&mut self, directory: Option<PathBuf>) -> Result<(), TensorError> {
+        self.processor.set_shader_cache_dir(directory)
+    }
+
+    /// Hits and misses of the shader cache, always zero with the `spirv` feature.
+    pub fn shader_cache_stats(&self) -> CacheStats {
+        self.processor.shader_cache_stats()
+    }
+
+    /// Removes every kernel from the shader cache, in memory and in its directory.
+    pub fn clear_shader_cache(&mut self) -> Result<(), TensorError> {
+        self.processor.clear_shader_cache()
+    }
+
+    /// Compiles the kernels of `ops` into the shader cache ahead of `build_many`. Only checks
+    /// `ops` with the `spirv` feature.
+    pub fn prewarm_shader_cache(&mut self, ops: &[Operation]) -> Result<(), TensorError> {
+        for op in ops {
+            if let TensorOperationResult::Error(x) = op.get_result() {
+                return Err(x)
+            }
+        }
+        self.processor.prewarm(ops)
+    }
+
+    /// Number of graph structures whose pipelines are cached. Building a graph with the same
+    /// operations, element types and shapes as a cached one only binds its tensors.
+    pub fn pipeline_cache_len(&self) -> usize {
+        self.processor.pipeline_cache_len()
+    }
+
+    /// Keeps the pipelines of at most `capacity` graph structures, evicting the least recently
+    /// built ones. Kernels that were already built are not affected.
+    pub fn set_pipeline_cache_capacity(&mut self, capacity: usize) {
+        self.processor.set_pipeline_cache_capacity(capacity)
+    }
+
+    pub fn clear_pipeline_cache(&mut self) {
+        self.processor.clear_pipeline_cache()
+    }
+
+    /// Builds a kernel for `op`, with a result of the shape the operation produces.
+    /// Fails if `op` is invalid or doesn't produce elements of type `T`.
+    pub fn build<T>(&mut self, op: Operation) -> Result<Compiled<T>, TensorError>
     where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
-        self.processor.build(op, result)
+        self.build_many(&[op])
     }
 
-    pub fn execute<'a, T>(&mut self, compiled: &Compiled<'a, T>) -> Result<Tensor<T>, TensorError> 
+    /// Builds one kernel computing all `ops`, sharing the bindings of tensors they have in common.
+    /// Every operation has to produce a result of type `T`.
+    pub fn build_many<T>(&mut self, ops: &[Operation]) -> Result<Compiled<T>, TensorError>
     where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
-        let intermediate = self.processor.execute(compiled);
-        
-        if let TensorOperationResult::Error(x) = intermediate {
+        Self::check_results::<T>(ops)?;
+        self.build_checked(ops)
+    }
+
+    /// `build_many` for operations `check_results` already accepted.
+    pub(crate) fn build_checked<T>(&mut self, ops: &[Operation]) -> Result<Compiled<T>, TensorError>
+    where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+        let results = ops.iter().map(|op| Tensor::zeros_from_shape(op.get_shape())).collect();
+        self.processor.build(ops, results)
+    }
+
+    /// Builds a kernel for `op` with the element type the operation produces.
+    pub fn build_inferred(&mut self, op: Operation) -> Result<InferredCompiled, TensorError> {
+        if let TensorOperationResult::Error(x) = op.get_result() {
             return Err(x)
         }
 
-        let result_shape = match compiled {
-            Compiled::GPU(x) => x.borrow().result_tensor.get_shape().to_vec()
+        Ok(match op.get_strength() {
+            0 => InferredCompiled::Int(self.build(op)?),
+            1 => InferredCompiled::UInt(self.build(op)?),
+            2 => InferredCompiled::Float(self.build(op)?),
+            _ => InferredCompiled::Double(self.build(op)?)
+        })
+    }
+
+    pub(crate) fn check_results<T>(ops: &[Operation]) -> Result<(), TensorError>
+    where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+        for op in ops {
+            let result = op.get_result();
+            if let TensorOperationResult::Error(x) = result {
+                return Err(x)
+            }
+            if result.get_own_strength() != T::strength() {
+                return Err(TensorError::TypeError(format!("Operation produces {}, not the requested result type", result.get_type_name())))
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds one kernel computing `op` and its directional derivative along `tangents`,
+    /// see `autodiff::jvp`. `execute_many` returns the output followed by the derivative.
+    pub fn build_jvp<T>(&mut self, op: &Operation, tangents: &[(&Tensor<T>, &Tensor<T>)]) -> Result<Compiled<T>, TensorError>
+    where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+        let (output, derivative) = jvp(op, tangents)?;
+        self.build_many(&[output, derivative])
+    }
+
+    /// Runs `compiled` and returns its first result.
+    pub fn execute<T>(&mut self, compiled: &Compiled<T>) -> Result<Tensor<T>, TensorError> 
+    where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+        self.execute_many(compiled)?.into_iter().next().ok_or_else(|| TensorError::Unimplemented("Kernel has no results".to_string()))
+    }
+
+    /// Binds each tensor to the `Input` of that name, see `Compiled::bind`, and runs `compiled`.
+    /// The tensors can have different element types, `TensorHolder::from` wraps any of them.
+    pub fn execute_with<T>(&mut self, compiled: &Compiled<T>, inputs: &[(&str, TensorHolder)]) -> Result<Tensor<T>, TensorError>
+    where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+        for (name, tensor) in inputs {
+            compiled.bind_holder(name, tensor)?;
+        }
+        self.execute(compiled)
+    }
+
+    /// Runs `compiled` once and returns a result per operation it was built from.
+    pub fn execute_many<T>(&mut self, compiled: &Compiled<T>) -> Result<Vec<Tensor<T>>, TensorError> 
+    where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+        self.run(compiled)?;
+        self.read_many(compiled)
+    }
+
+    /// Runs `compiled` without reading the results back, so that they can be
+    /// fed into further kernels on the device.
+    pub fn run<T>(&mut self, compiled: &Compiled<T>) -> Result<(), TensorError> 
+    where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+        self.processor.dispatch(compiled)
+    }
+
+    /// Reads the first result of the last run of `compiled`.
+    pub fn read<T>(&mut self, compiled: &Compiled<T>) -> Result<Tensor<T>, TensorError> 
+    where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+        self.read_many(compiled)?.into_iter().next().ok_or_else(|| TensorError::Unimplemented("Kernel has no results".to_string()))
+    }
+
+    /// Reads every result of the last run of `compiled`.
+    pub fn read_many<T>(&mut self, compiled: &Compiled<T>) -> Result<Vec<Tensor<T>>, TensorError> 
+    where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+        let intermediate = self.processor.read_back(compiled)?;
+
+        let result_shapes: Vec<Shape> = match compiled {
+            Compiled::GPU(x) => x.borrow().result_tensors.iter().map(|t| t.get_shape().to_vec()).collect(),
+            Compiled::Host(x) => x.borrow().result_tensors.iter().map(|t| t.get_shape().to_vec()).collect()
         };
 
-        let res: Result<Tensor<T>, TensorError> = T::get_tensor(intermediate, &result_shape);
+        intermediate.into_iter()
+            .zip(result_shapes.iter())
+            .map(|(result, shape)| T::get_tensor(result, shape))
+            .collect()
+    }
+
+    /// Makes `compiled` read its input `tensor` from the device buffer of `result` instead of
+    /// uploading it. The kernel producing `result` has to `run` before `compiled` to update it.
+    pub fn feed<T, U>(&mut self, compiled: &Compiled<T>, tensor: &Tensor<U>, result: &DeviceResult<U>) -> Result<(), TensorError>
+    where T: SupportedDataTypes + SupportedDataTypes<BindingType = T>,
+          U: SupportedDataTypes + SupportedDataTypes<BindingType = U> {
+        self.processor.link_input(compiled, &U::to_data_holder(tensor), result.buffer.clone(), &result.shape)
+    }
 
-        res
+    /// Runs a kernel from `build_inferred`.
+    pub fn execute_inferred(&mut self, compiled: &InferredCompiled) -> Result<TensorValue, TensorError> {
+        Ok(match compiled {
+            InferredCompiled::Int(x) => TensorValue::Int(self.execute(x)?),
+            InferredCompiled::UInt(x) => TensorValue::UInt(self.execute(x)?),
+            InferredCompiled::Float(x) => TensorValue::Float(self.execute(x)?),
+            InferredCompiled::Double(x) => TensorValue::Double(self.execute(x)?)
+        })
     }
-}
\ No newline at end of file
+}
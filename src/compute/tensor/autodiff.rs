@@ -0,0 +1,204 @@
+use super::{Operation, Shape, SingleValueOperation, SupportedDataTypes, Tensor, TensorError, TensorHolder, TensorOperationResult, TwoValueOperation};
+
+/// Reverse mode gradients of `loss` with respect to each tensor in `wrt`, as operations
+/// that build like any other.
+///
+/// A loss with more than one element is differentiated as the sum of its elements.
+/// Every gradient has the shape of its tensor: contributions to a broadcast tensor are
+/// summed up and tensors `loss` doesn't depend on get a gradient of zeros.
+pub fn grad<T>(loss: &Operation, wrt: &[&Tensor<T>]) -> Result<Vec<Operation>, TensorError>
+where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+    let targets: Vec<TensorHolder> = wrt.iter().map(|x| T::to_data_holder(x)).collect();
+    let mut gradients: Vec<Option<Operation>> = wrt.iter().map(|_| None).collect();
+
+    let seed = Operation::constant(1.0, loss.get_shape(), loss.get_strength());
+    backpropagate(loss, seed, &targets, &mut gradients)?;
+
+    Ok(gradients.into_iter().zip(wrt).map(|(gradient, tensor)| {
+        gradient.unwrap_or_else(|| Operation::constant(0.0, tensor.get_shape().to_vec(), T::strength()))
+    }).collect())
+}
+
+/// Passes the gradient `upstream` of the result of `op` on to its operands,
+/// accumulating it in `gradients` once it reaches one of the `targets`.
+fn backpropagate(op: &Operation, upstream: Operation, targets: &[TensorHolder], gradients: &mut [Option<Operation>]) -> Result<(), TensorError> {
+    let g = reduce_to_shape(upstream, &op.get_shape())?;
+
+    match op {
+        Operation::Var(x) => {
+            if let Some(k) = targets.iter().position(|t| *t == x.value) {
+                gradients[k] = Some(match gradients[k].take() {
+                    Some(sum) => Operation::dual(sum, g, TwoValueOperation::Add),
+                    None => g
+                });
+            }
+            Ok(())
+        },
+        Operation::Constant {..} => Ok(()),
+        Operation::SingleOp {value, result, op: single} => {
+            check_result(result)?;
+            let dv = match single {
+                // d sqrt(v) = 1 / (2 sqrt(v))
+                SingleValueOperation::SquareRoot => Operation::dual(g, Operation::dual(op.clone(), op.clone(), TwoValueOperation::Add), TwoValueOperation::Divide),
+                SingleValueOperation::Sum => g,
+                SingleValueOperation::Transpose => g.transpose()
+            };
+            backpropagate(value, dv, targets, gradients)
+        },
+        Operation::DualOp {left, right, result, op: dual} => {
+            check_result(result)?;
+            let l = || (**left).clone();
+            let r = || (**right).clone();
+
+            let (dl, dr) = match dual {
+                TwoValueOperation::Add => (g.clone(), g),
+                TwoValueOperation::Subtract => (g.clone(), negate(g)),
+                TwoValueOperation::Multiply => (
+                    Operation::dual(g.clone(), r(), TwoValueOperation::Multiply),
+                    Operation::dual(g, l(), TwoValueOperation::Multiply)
+                ),
+                // d (l / r) = dl / r - dr * l / (r * r)
+                TwoValueOperation::Divide => (
+                    Operation::dual(g.clone(), r(), TwoValueOperation::Divide),
+                    negate(Operation::dual(
+                        Operation::dual(g, l(), TwoValueOperation::Multiply),
+                        Operation::dual(r(), r(), TwoValueOperation::Multiply),
+                        TwoValueOperation::Divide
+                    ))
+                ),
+                // d (l r) = dl r + l dr, so dl = g rT and dr = lT g
+                TwoValueOperation::MatMul => (g.clone().matmul(r().transpose()), l().transpose().matmul(g))
+            };
+
+            backpropagate(left, dl, targets, gradients)?;
+            backpropagate(right, dr, targets, gradients)
+        }
+    }
+}
+
+/// Sums a gradient over the elements a single value was broadcast to, or broadcasts
+/// a single value gradient to every element of `shape`.
+fn reduce_to_shape(g: Operation, shape: &Shape) -> Result<Operation, TensorError> {
+    let g_shape = g.get_shape();
+    if g_shape == *shape {return Ok(g)}
+
+    if shape.iter().product::<usize>() == 1 {return Ok(g.sum())}
+    if g_shape.iter().product::<usize>() == 1 {
+        let strength = g.get_strength();
+        return Ok(Operation::dual(Operation::constant(0.0, shape.to_vec(), strength), g, TwoValueOperation::Add))
+    }
+
+    Err(TensorError::ShapeError(format!("Gradient of shape {:?} doesn't fit shape {:?}", g_shape, shape)))
+}
+
+fn negate(g: Operation) -> Operation {
+    let strength = g.get_strength();
+    Operation::dual(Operation::constant(0.0, vec![1], strength), g, TwoValueOperation::Subtract)
+}
+
+fn check_result(result: &TensorOperationResult) -> Result<(), TensorError> {
+    match result {
+        TensorOperationResult::Error(x) => Err(TensorError::ShapeError(format!("Can't differentiate an invalid operation: {:?}", x))),
+        _ => Ok(())
+    }
+}
+
+/// Forward mode: `op` together with its directional derivative along `tangents`, pairs of
+/// an input of `op` and its tangent of the same shape. Inputs without a tangent are held fixed.
+///
+/// The derivative is propagated as the second half of a dual number next to every node, so
+/// building both operations with `Processor::build_many` gives one kernel that computes each
+/// primal value once and shares it with the derivative.
+pub fn jvp<T>(op: &Operation, tangents: &[(&Tensor<T>, &Tensor<T>)]) -> Result<(Operation, Operation), TensorError>
+where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+    for (x, dx) in tangents {
+        if *x.get_shape() != *dx.get_shape() {
+            return Err(TensorError::ShapeError(format!("Tangent of shape {:?} doesn't fit shape {:?}", dx.get_shape(), x.get_shape())))
+        }
+    }
+
+    let targets: Vec<(TensorHolder, &Tensor<T>)> = tangents.iter().map(|(x, dx)| (T::to_data_holder(x), *dx)).collect();
+
+    let tangent = match propagate_tangent(op, &targets)? {
+        Some(t) => reduce_to_shape(t, &op.get_shape())?,
+        None => Operation::constant(0.0, op.get_shape(), op.get_strength())
+    };
+    Ok((op.clone(), tangent))
+}
+
+/// Tangent of `op`, with `None` standing for zero. A single value tangent is broadcast
+/// like the value it belongs to.
+fn propagate_tangent<T>(op: &Operation, targets: &[(TensorHolder, &Tensor<T>)]) -> Result<Option<Operation>, TensorError>
+where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+    match op {
+        Operation::Var(x) => Ok(targets.iter().find(|(t, _)| *t == x.value).map(|(_, dx)| Operation::from(*dx))),
+        Operation::Constant {..} => Ok(None),
+        Operation::SingleOp {value, result, op: single} => {
+            check_result(result)?;
+            let dv = match propagate_tangent(value, targets)? {
+                Some(dv) => dv,
+                None => return Ok(None)
+            };
+
+            Ok(Some(match single {
+                SingleValueOperation::SquareRoot => Operation::dual(dv, Operation::dual(op.clone(), op.clone(), TwoValueOperation::Add), TwoValueOperation::Divide),
+                SingleValueOperation::Sum => reduce_to_shape(dv, &value.get_shape())?.sum(),
+                SingleValueOperation::Transpose => reduce_to_shape(dv, &value.get_shape())?.transpose()
+            }))
+        },
+        Operation::DualOp {left, right, result, op: dual} => {
+            check_result(result)?;
+            let l = || (**left).clone();
+            let r = || (**right).clone();
+
+            let (dl, dr) = match (propagate_tangent(left, targets)?, propagate_tangent(right, targets)?) {
+                (None, None) => return Ok(None),
+                x => x
+            };
+
+            Ok(Some(match dual {
+                TwoValueOperation::Add => match (dl, dr) {
+                    (Some(dl), Some(dr)) => Operation::dual(dl, dr, TwoValueOperation::Add),
+                    (dl, dr) => dl.or(dr).unwrap()
+                },
+                TwoValueOperation::Subtract => match (dl, dr) {
+                    (Some(dl), Some(dr)) => Operation::dual(dl, dr, TwoValueOperation::Subtract),
+                    (Some(dl), None) => dl,
+                    (None, dr) => negate(dr.unwrap())
+                },
+                // d (l * r) = dl * r + l * dr
+                TwoValueOperation::Multiply => {
+                    let dl = dl.map(|dl| Operation::dual(dl, r(), TwoValueOperation::Multiply));
+                    let dr = dr.map(|dr| Operation::dual(l(), dr, TwoValueOperation::Multiply));
+                    match (dl, dr) {
+                        (Some(dl), Some(dr)) => Operation::dual(dl, dr, TwoValueOperation::Add),
+                        (dl, dr) => dl.or(dr).unwrap()
+                    }
+                },
+                // d (l / r) = dl / r - l * dr / (r * r)
+                TwoValueOperation::Divide => {
+                    let dl = dl.map(|dl| Operation::dual(dl, r(), TwoValueOperation::Divide));
+                    let dr = dr.map(|dr| Operation::dual(
+                        Operation::dual(l(), dr, TwoValueOperation::Multiply),
+                        Operation::dual(r(), r(), TwoValueOperation::Multiply),
+                        TwoValueOperation::Divide
+                    ));
+                    match (dl, dr) {
+                        (Some(dl), Some(dr)) => Operation::dual(dl, dr, TwoValueOperation::Subtract),
+                        (Some(dl), None) => dl,
+                        (None, dr) => negate(dr.unwrap())
+                    }
+                },
+                // d (l r) = dl r + l dr
+                TwoValueOperation::MatMul => {
+                    let dl = dl.map(|dl| reduce_to_shape(dl, &left.get_shape())).transpose()?.map(|dl| dl.matmul(r()));
+                    let dr = dr.map(|dr| reduce_to_shape(dr, &right.get_shape())).transpose()?.map(|dr| l().matmul(dr));
+                    match (dl, dr) {
+                        (Some(dl), Some(dr)) => Operation::dual(dl, dr, TwoValueOperation::Add),
+                        (dl, dr) => dl.or(dr).unwrap()
+                    }
+                }
+            }))
+        }
+    }
+}
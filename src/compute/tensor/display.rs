@@ -0,0 +1,85 @@
+use std::fmt;
+use std::fmt::Write;
+
+use super::{Operation, TensorHolder, TensorOperationResult};
+
+impl Operation {
+    /// Renders the operation tree in Graphviz DOT format.
+    pub fn to_dot(&self) -> String {
+        let mut s = String::new();
+        let mut next: usize = 0;
+        let mut bindings = Vec::new();
+
+        writeln!(&mut s, "digraph operation {{").unwrap();
+        self.write_dot(&mut s, &mut next, &mut bindings);
+        writeln!(&mut s, "}}").unwrap();
+        s
+    }
+
+    fn write_dot(&self, s: &mut String, next: &mut usize, bindings: &mut Vec<TensorHolder>) -> usize {
+        let id = *next;
+        *next += 1;
+
+        writeln!(s, "    n{} [label=\"{}\"];", id, self.get_label(bindings).replace('"', "\\\"").replace('\n', "\\n")).unwrap();
+
+        match self {
+            Operation::Var(_) | Operation::Constant {..} => {},
+            Operation::SingleOp {value, ..} => {
+                let child = value.write_dot(s, next, bindings);
+                writeln!(s, "    n{} -> n{};", id, child).unwrap();
+            },
+            Operation::DualOp {left, right, ..} => {
+                let l = left.write_dot(s, next, bindings);
+                let r = right.write_dot(s, next, bindings);
+                writeln!(s, "    n{} -> n{} [label=\"left\"];", id, l).unwrap();
+                writeln!(s, "    n{} -> n{} [label=\"right\"];", id, r).unwrap();
+            }
+        }
+
+        id
+    }
+
+    /// One line describing this node without its operands. Tensors are numbered like the
+    /// bindings of a `Graph`, in order of first use in `bindings`.
+    fn get_label(&self, bindings: &mut Vec<TensorHolder>) -> String {
+        match self {
+            Operation::Var(x) => {
+                let id = match bindings.iter().position(|y| *y == x.value) {
+                    Some(id) => id,
+                    None => {bindings.push(x.value.copy()); bindings.len() - 1}
+                };
+                format!("binding {}: {} {:?}", id, x.value.get_type_name(), x.value.get_shape())
+            },
+            Operation::Constant {value, result} => format!("{} -> {}", value, Self::describe_result(result)),
+            Operation::SingleOp {result, op, ..} => format!("{:?} -> {}", op, Self::describe_result(result)),
+            Operation::DualOp {result, op, ..} => format!("{:?} -> {}", op, Self::describe_result(result))
+        }
+    }
+
+    fn describe_result(result: &TensorOperationResult) -> String {
+        match result {
+            TensorOperationResult::Error(x) => format!("{:?}", x),
+            _ => format!("{} {:?}", result.get_type_name(), result.get_own_shape())
+        }
+    }
+
+    fn fmt_indented(&self, f: &mut fmt::Formatter<'_>, depth: usize, bindings: &mut Vec<TensorHolder>) -> fmt::Result {
+        writeln!(f, "{:indent$}{}", "", self.get_label(bindings), indent = depth * 2)?;
+
+        match self {
+            Operation::Var(_) | Operation::Constant {..} => Ok(()),
+            Operation::SingleOp {value, ..} => value.fmt_indented(f, depth + 1, bindings),
+            Operation::DualOp {left, right, ..} => {
+                left.fmt_indented(f, depth + 1, bindings)?;
+                right.fmt_indented(f, depth + 1, bindings)
+            }
+        }
+    }
+}
+
+/// Prints the operation tree, one node per line with its operands indented below.
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_indented(f, 0, &mut Vec::new())
+    }
+}
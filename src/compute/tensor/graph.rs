@@ -0,0 +1,470 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+
+use super::{BindingInfo, DataType, Graph, MemoryPlan, Node, NodeId, Operation, Shape, SingleValueOperation, TensorBinding, TensorError, TwoValueOperation, UniformLayout, INPUT_NAME};
+
+#[derive(PartialEq, Eq, Hash)]
+pub(crate) enum NodeKey {
+    Input(u32),
+    Constant(u64, usize, Shape),
+    Single(SingleValueOperation, NodeId),
+    Dual(TwoValueOperation, NodeId, NodeId)
+}
+
+/// Element types and shapes of the inputs and nodes of a graph, the kinds of its nodes and its
+/// outputs, everything its kernels depend on but the values of the inputs.
+#[derive(PartialEq, Eq, Hash)]
+pub(crate) struct StructuralKey {
+    inputs: Vec<(usize, Shape)>,
+    nodes: Vec<(usize, Shape, NodeKey)>,
+    outputs: Vec<NodeId>
+}
+
+/// Compiler `build_shader` uses, part of the key kernels are cached on disk with.
+#[cfg(feature = "spirv")]
+pub(crate) const SHADER_COMPILER: &str = "rspirv 0.11";
+#[cfg(all(feature = "wgsl", not(feature = "spirv")))]
+pub(crate) const SHADER_COMPILER: &str = "naga 0.13 wgsl";
+#[cfg(not(any(feature = "wgsl", feature = "spirv")))]
+pub(crate) const SHADER_COMPILER: &str = "shaderc 0.7 glsl";
+
+/// Workgroups a dispatch can have in every dimension on any device.
+pub(crate) const MAX_WORKGROUPS: u32 = 65535;
+
+impl NodeKey {
+    pub(crate) fn dual(op: TwoValueOperation, left: NodeId, right: NodeId) -> NodeKey {
+        match op {
+            TwoValueOperation::Add | TwoValueOperation::Multiply => NodeKey::Dual(op, left.min(right), left.max(right)),
+            _ => NodeKey::Dual(op, left, right)
+        }
+    }
+}
+
+impl Graph {
+    pub fn from_operation(op: &Operation) -> Graph {
+        Graph::from_operations(std::slice::from_ref(op))
+    }
+
+    /// Lowers several operations into one graph with an output per operation.
+    pub fn from_operations(ops: &[Operation]) -> Graph {
+        let mut graph = Graph::empty();
+        let mut known = HashMap::<NodeKey, NodeId>::new();
+
+        for op in ops {
+            let output = graph.lower(op, &mut known);
+            graph.outputs.push(output);
+        }
+        graph
+    }
+
+    pub(crate) fn empty() -> Graph {
+        Graph {nodes: Vec::new(), consumers: Vec::new(), inputs: Vec::new(), outputs: Vec::new()}
+    }
+
+    fn lower(&mut self, op: &Operation, known: &mut HashMap<NodeKey, NodeId>) -> NodeId {
+        match op {
+            Operation::Var(x) => {
+                let binding = self.add_input(x);
+                self.insert(NodeKey::Input(binding), Node::Input(binding), &[], known)
+            },
+            Operation::Constant {value, result} => {
+                let key = NodeKey::Constant(value.to_bits(), result.get_own_strength(), result.get_own_shape());
+                self.insert(key, Node::Constant {value: *value, result: result.copy()}, &[], known)
+            },
+            Operation::SingleOp {value, result, op} => {
+                let value = self.lower(value, known);
+                self.insert(NodeKey::Single(*op, value), Node::SingleOp {value, result: result.copy(), op: *op}, &[value], known)
+            },
+            Operation::DualOp {left, right, result, op} => {
+                let left = self.lower(left, known);
+                let right = self.lower(right, known);
+                self.insert(NodeKey::dual(*op, left, right), Node::DualOp {left, right, result: result.copy(), op: *op}, &[left, right], known)
+            }
+        }
+    }
+
+    /// Binds the tensor of `binding`, reusing the binding id if it is already an input.
+    pub(crate) fn add_input(&mut self, binding: &TensorBinding) -> u32 {
+        match self.inputs.iter().find(|y| y.value == binding.value) {
+            Some(y) => y.id,
+            None => {
+                let mut input = binding.copy();
+                input.id = self.inputs.len() as u32;
+                self.inputs.push(input);
+                self.inputs.len() as u32 - 1
+            }
+        }
+    }
+
+    pub(crate) fn insert(&mut self, key: NodeKey, node: Node, operands: &[NodeId], known: &mut HashMap<NodeKey, NodeId>) -> NodeId {
+        if let Some(id) = known.get(&key) {
+            return *id
+        }
+
+        let id = self.nodes.len();
+        self.nodes.push(node);
+        self.consumers.push(Vec::new());
+
+        for operand in operands {
+            if !self.consumers[*operand].contains(&id) {
+                self.consumers[*operand].push(id);
+            }
+        }
+
+        known.insert(key, id);
+        id
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub fn outputs(&self) -> &[NodeId] {
+        &self.outputs
+    }
+
+    /// Nodes that use the result of `id` as an operand.
+    pub fn consumers(&self, id: NodeId) -> &[NodeId] {
+        &self.consumers[id]
+    }
+
+    pub fn input_count(&self) -> usize {
+        self.inputs.len()
+    }
+
+    /// Binding ids of the result buffers, one per output placed after all inputs.
+    pub fn result_bindings(&self) -> Vec<u32> {
+        (0..self.outputs.len()).map(|i| (self.inputs.len() + i) as u32).collect()
+    }
+
+    /// The binding table: every distinct tensor in order of first use, then the results.
+    pub fn bindings(&self) -> Vec<BindingInfo> {
+        let inputs = self.inputs.iter().map(|x| BindingInfo {
+            binding: x.id,
+            name: x.name.clone(),
+            is_result: false,
+            data_type: DataType::from_strength(x.value.get_strength()),
+            shape: x.value.get_shape()
+        });
+
+        let results = self.result_bindings().into_iter().zip(&self.outputs).map(|(binding, output)| BindingInfo {
+            binding,
+            name: None,
+            is_result: true,
+            data_type: DataType::from_strength(self.get_node_strength(*output)),
+            shape: self.get_node_shape(*output)
+        });
+
+        inputs.chain(results).collect()
+    }
+
+    /// Hash of the operations, element types and shapes of the graph, but not of the tensors
+    /// bound to it. Graphs with the same structure compile to the same kernels.
+    pub fn structural_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.structural_key().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// What `structural_hash` is computed from, to tell apart graphs whose hashes collide.
+    pub(crate) fn structural_key(&self) -> StructuralKey {
+        let inputs = self.inputs.iter().map(|x| (x.value.get_strength(), x.value.get_shape())).collect();
+        let nodes = self.nodes.iter().enumerate().map(|(id, node)| {
+            let strength = self.get_node_strength(id);
+            let shape = self.get_node_shape(id);
+            let key = match node {
+                Node::Input(x) => NodeKey::Input(*x),
+                Node::Constant {value, ..} => NodeKey::Constant(value.to_bits(), strength, shape.clone()),
+                Node::SingleOp {value, op, ..} => NodeKey::Single(*op, *value),
+                // unlike `NodeKey::dual`, operands of commutative operations keep their order
+                Node::DualOp {left, right, op, ..} => NodeKey::Dual(*op, *left, *right)
+            };
+            (strength, shape, key)
+        }).collect();
+
+        StructuralKey {inputs, nodes, outputs: self.outputs.clone()}
+    }
+
+    pub(crate) fn get_operands(&self, id: NodeId) -> Vec<NodeId> {
+        match &self.nodes[id] {
+            Node::Input(_) | Node::Constant {..} => Vec::new(),
+            Node::SingleOp {value, ..} => vec![*value],
+            Node::DualOp {left, right, ..} => vec![*left, *right]
+        }
+    }
+
+    /// Whether node `id` only reads the element of its operands it computes itself.
+    pub(crate) fn is_elementwise(&self, id: NodeId) -> bool {
+        !matches!(self.nodes[id],
+            Node::SingleOp {op: SingleValueOperation::Sum | SingleValueOperation::Transpose, ..} | Node::DualOp {op: TwoValueOperation::MatMul, ..})
+    }
+
+    pub(crate) fn get_node_strength(&self, id: NodeId) -> usize {
+        match &self.nodes[id] {
+            Node::Input(x) => self.inputs[*x as usize].value.get_strength(),
+            Node::Constant {result, ..} | Node::SingleOp {result, ..} | Node::DualOp {result, ..} => result.get_own_strength()
+        }
+    }
+
+    pub(crate) fn get_node_shape(&self, id: NodeId) -> Shape {
+        match &self.nodes[id] {
+            Node::Input(x) => self.inputs[*x as usize].value.get_shape(),
+            Node::Constant {result, ..} | Node::SingleOp {result, ..} | Node::DualOp {result, ..} => result.get_own_shape()
+        }
+    }
+
+    /// Evaluates every element of every output, computing each node exactly once.
+    pub(crate) fn evaluate_host(&self) -> Vec<Vec<f64>> {
+        let mut values = Vec::<Vec<f64>>::with_capacity(self.nodes.len());
+
+        for id in 0..self.nodes.len() {
+            let value = {
+                let operands: Vec<&[f64]> = self.get_operands(id).iter().map(|x| values[*x].as_slice()).collect();
+                self.evaluate_node_host(id, &operands)
+            };
+            values.push(value);
+        }
+
+        self.outputs.iter().map(|x| values[*x].clone()).collect()
+    }
+
+    /// Rows and columns of the matrix node `id` produces.
+    pub(crate) fn get_matrix_shape(&self, id: NodeId) -> (usize, usize) {
+        let shape = self.get_node_shape(id);
+        (shape[0], shape[1])
+    }
+
+    /// Every element of node `id`, from every element of its operands. Operands of a single
+    /// value are broadcast.
+    pub(crate) fn evaluate_node_host(&self, id: NodeId, operands: &[&[f64]]) -> Vec<f64> {
+        let length: usize = self.get_node_shape(id).iter().product();
+        let at = |x: &[f64], index: usize| if x.len() == 1 {x[0]} else {x[index]};
+
+        match &self.nodes[id] {
+            Node::Input(x) => (0..length).map(|i| self.inputs[*x as usize].value.get_f64(i)).collect(),
+            Node::Constant {value, ..} => vec![*value; length],
+            Node::SingleOp {op: SingleValueOperation::Sum, ..} => vec![operands[0].iter().sum()],
+            Node::SingleOp {op: SingleValueOperation::Transpose, ..} => {
+                let (rows, columns) = self.get_matrix_shape(id);
+                (0..length).map(|i| operands[0][(i % columns) * rows + i / columns]).collect()
+            },
+            Node::SingleOp {op, ..} => (0..length).map(|i| op.apply_f64(at(operands[0], i))).collect(),
+            Node::DualOp {right, op: TwoValueOperation::MatMul, ..} => {
+                let (k, n) = self.get_matrix_shape(*right);
+                (0..length).map(|i| (0..k).map(|j| operands[0][i / n * k + j] * operands[1][j * n + i % n]).sum()).collect()
+            },
+            Node::DualOp {op, ..} => (0..length).map(|i| op.apply_f64(at(operands[0], i), at(operands[1], i))).collect()
+        }
+    }
+}
+
+impl Graph {
+    /// GLSL compute shader for kernel `stage` of `plan`, with the inputs bound at their binding
+    /// ids, then the result buffers of `result_bindings` and the pool at `pool_binding`.
+    /// Outputs computed in this kernel are written to their result.
+    pub fn build_glsl(&self, plan: &MemoryPlan, stage: usize, workgroup_size: u32) -> String {
+        if self.get_vector_width(plan, stage) > 1 {
+            return self.build_glsl_vector(plan, stage, workgroup_size)
+        }
+
+        let inputs = &self.inputs;
+        let bindings = self.result_bindings();
+        let uniforms = self.plan_uniforms(plan, workgroup_size);
+
+        let mut s = String::new();
+        writeln!(&mut s, "#version 450").unwrap();
+        writeln!(&mut s, "layout(local_size_x = {}) in;", workgroup_size).unwrap();
+
+        writeln!(&mut s).unwrap();
+
+        // scalars are read from the uniform buffer
+        for i in inputs.iter().filter(|x| !x.value.is_single()) {
+            writeln!(&mut s, "readonly layout(set = 0, binding = {}) buffer b{} {{", (*i).id, (*i).id).unwrap();
+            writeln!(&mut s, "{} {}{};", (*i).get_type_glsl(), INPUT_NAME, (*i).id).unwrap();
+            writeln!(&mut s, "}};").unwrap();
+            writeln!(&mut s).unwrap();
+        }
+
+        for (i, binding) in bindings.iter().enumerate() {
+            if plan.get_stage(self.outputs[i]) != stage {continue}
+            writeln!(&mut s, "layout(set = 0, binding = {}) buffer b{} {{", binding, binding).unwrap();
+            writeln!(&mut s, "{}[] result{};", self.get_node_type_glsl(self.outputs[i]), i).unwrap();
+            writeln!(&mut s, "}};").unwrap();
+            writeln!(&mut s).unwrap();
+        }
+
+        // a pool buffer holds at most one intermediate a kernel reads or writes
+        for id in 0..self.nodes.len() {
+            if let Some(slot) = plan.get_slot(id) {
+                if !self.is_used_in_stage(plan, id, stage) {continue}
+                let binding = self.pool_binding(slot);
+                writeln!(&mut s, "layout(set = 0, binding = {}) buffer b{} {{", binding, binding).unwrap();
+                writeln!(&mut s, "{}[] m{};", self.get_node_type_glsl(id), slot).unwrap();
+                writeln!(&mut s, "}};").unwrap();
+                writeln!(&mut s).unwrap();
+            }
+        }
+
+        self.write_parameters_glsl(&mut s, &uniforms);
+
+        writeln!(&mut s, "void main() {{").unwrap();
+        Self::write_index_glsl(&mut s, stage);
+        for (id, node) in self.nodes.iter().enumerate() {
+            if plan.get_stage(id) != stage {continue}
+            match node {
+                Node::Input(_) | Node::Constant {..} => {},
+                Node::SingleOp {value, result, op: SingleValueOperation::Sum} => {
+                    // the operand is an input or was stored by an earlier kernel
+                    let length: usize = self.get_node_shape(*value).iter().product();
+                    writeln!(&mut s, "{} t{} = {};", result.get_element_type_glsl(), id, result.get_literal_glsl(0.0)).unwrap();
+                    writeln!(&mut s, "for (uint i{} = 0u; i{} < {}u; i{}++) {{", id, id, length, id).unwrap();
+                    writeln!(&mut s, "t{} += {};", id, self.get_operand_glsl(plan, stage, *value, &format!("i{}", id))).unwrap();
+                    writeln!(&mut s, "}}").unwrap();
+                },
+                Node::SingleOp {value, result, op: SingleValueOperation::Transpose} => {
+                    let (rows, columns) = self.get_matrix_shape(id);
+                    writeln!(&mut s, "uint j{} = min(index, {}u);", id, rows * columns - 1).unwrap();
+                    let element = format!("(j{} % {}u) * {}u + j{} / {}u", id, columns, rows, id, columns);
+                    writeln!(&mut s, "{} t{} = {};", result.get_element_type_glsl(), id, self.get_operand_glsl(plan, stage, *value, &element)).unwrap();
+                },
+                Node::SingleOp {value, result, op: SingleValueOperation::SquareRoot} => {
+                    writeln!(&mut s, "{} t{} = sqrt({});", result.get_element_type_glsl(), id, self.get_operand_glsl(plan, stage, *value, "index")).unwrap();
+                },
+                Node::DualOp {left, right, result, op: TwoValueOperation::MatMul} => {
+                    // invocations past the end compute the last element instead of reading past the operands
+                    let (k, n) = self.get_matrix_shape(*right);
+                    let element = result.get_element_type_glsl();
+                    writeln!(&mut s, "uint j{} = min(index, {}u);", id, self.get_matrix_shape(id).0 * n - 1).unwrap();
+                    writeln!(&mut s, "{} t{} = {};", element, id, result.get_literal_glsl(0.0)).unwrap();
+                    writeln!(&mut s, "for (uint i{} = 0u; i{} < {}u; i{}++) {{", id, id, k, id).unwrap();
+                    let l = self.get_operand_glsl(plan, stage, *left, &format!("j{} / {}u * {}u + i{}", id, n, k, id));
+                    let r = self.get_operand_glsl(plan, stage, *right, &format!("i{} * {}u + j{} % {}u", id, n, id, n));
+                    writeln!(&mut s, "t{} += {}({}) * {}({});", id, element, l, element, r).unwrap();
+                    writeln!(&mut s, "}}").unwrap();
+                },
+                Node::DualOp {left, right, result, op} => {
+                    writeln!(&mut s, "{} t{} = ({} {:?} {});", result.get_element_type_glsl(), id, self.get_operand_glsl(plan, stage, *left, "index"), op, self.get_operand_glsl(plan, stage, *right, "index")).unwrap();
+                }
+            }
+
+            if let Some(slot) = plan.get_slot(id) {
+                let length: usize = self.get_node_shape(id).iter().product();
+                writeln!(&mut s, "if (index < {}u) m{}[index] = t{};", length, slot, id).unwrap();
+            }
+        }
+        for (i, output) in self.outputs.iter().enumerate() {
+            if plan.get_stage(*output) != stage {continue}
+            writeln!(&mut s, "if (index < uint(result{}.length())) result{}[index] = {};", i, i, self.get_operand_glsl(plan, stage, *output, "index")).unwrap();
+        }
+        writeln!(&mut s, "}}").unwrap();
+
+        s
+    }
+
+    /// The uniform buffer of `Graph::plan_uniforms`.
+    pub(crate) fn write_parameters_glsl(&self, s: &mut String, uniforms: &UniformLayout) {
+        writeln!(s, "layout(std140, set = 0, binding = {}) uniform Parameters {{", uniforms.binding).unwrap();
+        writeln!(s, "uvec4 dims[{}];", uniforms.dims.len()).unwrap();
+        for i in self.inputs.iter().filter(|x| x.value.is_single()) {
+            writeln!(s, "{} {}{};", i.get_type_glsl(), INPUT_NAME, i.id).unwrap();
+        }
+        writeln!(s, "}};").unwrap();
+        writeln!(s).unwrap();
+    }
+
+    /// Index of the invocation within kernel `stage`, returning from those past the last.
+    pub(crate) fn write_index_glsl(s: &mut String, stage: usize) {
+        writeln!(s, "uvec4 size = dims[{}];", stage).unwrap();
+        writeln!(s, "uint index = gl_GlobalInvocationID.x + gl_GlobalInvocationID.y * size.y + gl_GlobalInvocationID.z * size.z;").unwrap();
+        writeln!(s, "if (index >= size.x) return;").unwrap();
+    }
+
+    /// Elements kernel `stage` computes, as many as the largest result or intermediate it writes.
+    pub(crate) fn get_stage_length(&self, plan: &MemoryPlan, stage: usize) -> usize {
+        let written = (0..self.nodes.len()).filter(|x| plan.get_slot(*x).is_some() || self.outputs.contains(x));
+        written.filter(|x| plan.get_stage(*x) == stage).map(|x| self.get_node_shape(x).iter().product()).max().unwrap_or(0)
+    }
+
+    /// Workgroups to dispatch kernel `stage` with. Once more than `MAX_WORKGROUPS` would be
+    /// needed they are spread over the y and then the z dimension.
+    pub fn get_workgroup_count(&self, plan: &MemoryPlan, stage: usize, workgroup_size: u32) -> [u32; 3] {
+        Self::get_workgroup_count_for(self.get_stage_invocations(plan, stage), workgroup_size)
+    }
+
+    pub(crate) fn get_workgroup_count_for(length: usize, workgroup_size: u32) -> [u32; 3] {
+        let groups = length.div_ceil(workgroup_size as usize).max(1);
+        let x = groups.min(MAX_WORKGROUPS as usize);
+        let rest = groups.div_ceil(x);
+        let y = rest.min(MAX_WORKGROUPS as usize);
+        let z = rest.div_ceil(y);
+        [x as u32, y as u32, z as u32]
+    }
+
+    /// Elements between invocations one apart in y and in z, so that every invocation of a
+    /// multi-dimensional dispatch computes its own element. Both are zero for a 1-D dispatch.
+    pub(crate) fn get_index_strides(length: usize, workgroup_size: u32) -> (u32, u32) {
+        let [x, y, z] = Self::get_workgroup_count_for(length, workgroup_size);
+        if y == 1 && z == 1 {
+            return (0, 0)
+        }
+        (x * workgroup_size, (x * workgroup_size).saturating_mul(y))
+    }
+
+    /// Binding id of buffer `slot` of the intermediate pool, placed after the results.
+    pub(crate) fn pool_binding(&self, slot: usize) -> u32 {
+        (self.inputs.len() + self.outputs.len() + slot) as u32
+    }
+
+    /// Whether kernel `stage` computes node `id` or reads it.
+    pub(crate) fn is_used_in_stage(&self, plan: &MemoryPlan, id: NodeId, stage: usize) -> bool {
+        plan.get_stage(id) == stage || self.consumers[id].iter().any(|x| plan.get_stage(*x) == stage)
+    }
+
+    pub(crate) fn get_node_type_glsl(&self, id: NodeId) -> String {
+        match &self.nodes[id] {
+            Node::Input(x) => self.inputs[*x as usize].value.get_element_type_glsl(),
+            Node::Constant {result, ..} | Node::SingleOp {result, ..} | Node::DualOp {result, ..} => result.get_element_type_glsl()
+        }
+    }
+
+    /// Node `id` at element `index` as seen by kernel `stage`. Values of earlier kernels are
+    /// read from the pool, where single values are broadcast like inputs.
+    pub(crate) fn get_operand_glsl(&self, plan: &MemoryPlan, stage: usize, id: NodeId, index: &str) -> String {
+        match &self.nodes[id] {
+            Node::Input(x) => self.inputs[*x as usize].get_value_glsl(index),
+            Node::Constant {value, result} => result.get_literal_glsl(*value),
+            _ if plan.get_stage(id) == stage => format!("t{}", id),
+            _ => {
+                let slot = plan.get_slot(id).unwrap();
+                if self.get_node_shape(id).iter().product::<usize>() == 1 {format!("m{}[0]", slot)} else {format!("m{}[{}]", slot, index)}
+            }
+        }
+    }
+
+    /// Source of kernel `stage` in the shading language the crate is built for.
+    pub fn build_source(&self, plan: &MemoryPlan, stage: usize, workgroup_size: u32) -> Result<String, TensorError> {
+        if cfg!(feature = "wgsl") {self.build_wgsl(plan, stage, workgroup_size)} else {Ok(self.build_glsl(plan, stage, workgroup_size))}
+    }
+
+    #[cfg(all(feature = "wgsl", not(feature = "spirv")))]
+    pub(crate) fn build_shader(src: &str) -> Vec<u8> {
+        let module = naga::front::wgsl::parse_str(src).unwrap();
+        let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::empty())
+            .validate(&module).unwrap();
+        let words = naga::back::spv::write_vec(&module, &info, &naga::back::spv::Options::default(), None).unwrap();
+
+        bytemuck::cast_slice(&words).to_owned()
+    }
+
+    #[cfg(not(any(feature = "wgsl", feature = "spirv")))]
+    pub(crate) fn build_shader(src: &str) -> Vec<u8> {
+        let mut compiler = shaderc::Compiler::new().unwrap();
+        let binary_result = compiler.compile_into_spirv(
+            src, shaderc::ShaderKind::Compute,
+            "shader.glsl", "main", None).unwrap();
+
+        binary_result.as_binary_u8().to_owned()
+    }
+}
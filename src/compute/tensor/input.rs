@@ -0,0 +1,42 @@
+use super::{DataType, Input, Operation, Shape, Tensor, TensorBinding, TensorValue};
+
+impl Input {
+    /// A placeholder holding zeros of `dtype` and `shape` until a tensor is bound to it.
+    pub fn new(name: &str, dtype: DataType, shape: Shape) -> Input {
+        let value = match dtype {
+            DataType::Int => TensorValue::Int(Tensor::zeros_from_shape(shape)),
+            DataType::UInt => TensorValue::UInt(Tensor::zeros_from_shape(shape)),
+            DataType::Float => TensorValue::Float(Tensor::zeros_from_shape(shape)),
+            DataType::Double => TensorValue::Double(Tensor::zeros_from_shape(shape))
+        };
+
+        Input {name: name.to_string(), value}
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_data_type(&self) -> DataType {
+        match self.value {
+            TensorValue::Int(_) => DataType::Int,
+            TensorValue::UInt(_) => DataType::UInt,
+            TensorValue::Float(_) => DataType::Float,
+            TensorValue::Double(_) => DataType::Double
+        }
+    }
+
+    pub fn get_shape(&self) -> Shape {
+        self.value.get_holder().get_shape()
+    }
+
+    pub fn op(&self) -> Operation {
+        Operation::from(self)
+    }
+}
+
+impl<'a> From<&'a Input> for Operation {
+    fn from(input: &'a Input) -> Operation {
+        Operation::Var(Box::new(TensorBinding::named(input.value.get_holder(), &input.name)))
+    }
+}
@@ -0,0 +1,126 @@
+use super::{Graph, MemoryPlan, Node, NodeId, SingleValueOperation};
+use super::vectorize::get_padded_size;
+
+impl Graph {
+    /// Splits the graph into kernels and assigns the intermediates passed between them to a
+    /// pool of buffers.
+    ///
+    /// A sum, transpose or matrix product reads other elements of its operands than the one it
+    /// computes, so an operand that has to be computed first ends its kernel and is written to
+    /// a buffer. A sum also gets a kernel of its own, which a single invocation runs, and its
+    /// consumers read it from a buffer as a single value. Nodes used by a later kernel are
+    /// stored the same way, and two intermediates share a buffer when no kernel needs both of
+    /// them.
+    pub fn plan_memory(&self) -> MemoryPlan {
+        let stages = self.get_stages();
+        let stage_count = stages.iter().copied().max().map_or(1, |x| x + 1);
+
+        // last kernel reading each node, for the computed nodes a later kernel depends on
+        let mut live = Vec::<(NodeId, usize)>::new();
+        for id in 0..self.nodes.len() {
+            if !self.is_computed(id) {continue}
+
+            let end = self.consumers[id].iter().map(|x| stages[*x]).max().unwrap_or(stages[id]);
+            if end > stages[id] {
+                live.push((id, end));
+            }
+        }
+        live.sort_by_key(|(id, _)| (stages[*id], *id));
+
+        let mut slots = vec![None; self.nodes.len()];
+        let mut pool = Vec::<u64>::new();
+        let mut free_after = Vec::<usize>::new();
+
+        for (id, end) in live {
+            let size = self.get_node_mem_size(id);
+            let slot = match (0..pool.len()).find(|x| free_after[*x] < stages[id]) {
+                Some(x) => x,
+                None => {pool.push(0); free_after.push(0); pool.len() - 1}
+            };
+            pool[slot] = pool[slot].max(size);
+            free_after[slot] = end;
+            slots[id] = Some(slot);
+        }
+
+        // storage buffers are padded, scalars live in the uniform buffer
+        let input_size: u64 = self.inputs.iter().filter(|x| !x.value.is_single()).map(|x| {
+            get_padded_size(x.value.get_shape().iter().product::<usize>() as u64 * Self::get_element_size(x.value.get_strength()))
+        }).sum();
+        let uniform_size = self.pack_scalars(stage_count).1;
+        // every result has an unpadded staging buffer to be read back through
+        let result_size: u64 = self.outputs.iter().map(|x| get_padded_size(self.get_node_mem_size(*x)) + self.get_node_mem_size(*x)).sum();
+
+        MemoryPlan {stages, stage_count, slots, pool, io_size: input_size + uniform_size + result_size}
+    }
+
+    /// Kernel every node is computed in, counting from zero. Sums run in kernels of their own
+    /// with a single invocation, so they are placed at odd stages and everything else at even
+    /// ones before the stages without nodes are dropped.
+    fn get_stages(&self) -> Vec<usize> {
+        let mut stages = Vec::<usize>::with_capacity(self.nodes.len());
+
+        for (id, node) in self.nodes.iter().enumerate() {
+            let stage = match node {
+                Node::Input(_) | Node::Constant {..} => 0,
+                _ => {
+                    let elementwise = self.is_elementwise(id);
+                    // an operand computed in the same kernel is only available at the same element
+                    let stage = self.get_operands(id).iter().map(|x| {
+                        if self.is_sum(*x) || (!elementwise && self.is_computed(*x)) {stages[*x] + 1} else {stages[*x]}
+                    }).max().unwrap_or(0);
+                    if (stage % 2 == 1) == self.is_sum(id) {stage} else {stage + 1}
+                }
+            };
+            stages.push(stage);
+        }
+
+        let mut used: Vec<usize> = (0..self.nodes.len()).filter(|x| self.is_computed(*x)).map(|x| stages[x]).collect();
+        used.sort_unstable();
+        used.dedup();
+        stages.iter().map(|x| used.iter().filter(|y| *y < x).count()).collect()
+    }
+
+    fn is_sum(&self, id: NodeId) -> bool {
+        matches!(self.nodes[id], Node::SingleOp {op: SingleValueOperation::Sum, ..})
+    }
+
+    /// Inputs and constants are available to every kernel without being computed.
+    pub(crate) fn is_computed(&self, id: NodeId) -> bool {
+        !matches!(self.nodes[id], Node::Input(_) | Node::Constant {..})
+    }
+
+    pub(crate) fn get_node_mem_size(&self, id: NodeId) -> u64 {
+        self.get_node_shape(id).iter().product::<usize>() as u64 * Self::get_element_size(self.get_node_strength(id))
+    }
+
+    fn get_element_size(strength: usize) -> u64 {
+        if strength == 3 {8} else {4}
+    }
+}
+
+impl MemoryPlan {
+    /// Number of kernels the graph runs as.
+    pub fn get_stage_count(&self) -> usize {
+        self.stage_count
+    }
+
+    /// Sizes in bytes of the buffers intermediates are stored in.
+    pub fn get_pool(&self) -> &[u64] {
+        &self.pool
+    }
+
+    /// Bytes of device memory the compiled graph holds: its inputs, the uniform buffer, results
+    /// with their staging buffers and the intermediate pool.
+    pub fn get_peak_memory(&self) -> u64 {
+        self.io_size + self.pool.iter().sum::<u64>()
+    }
+
+    pub(crate) fn get_stage(&self, id: NodeId) -> usize {
+        self.stages[id]
+    }
+
+    /// Pool buffer node `id` is stored in, if a later kernel reads it.
+    pub(crate) fn get_slot(&self, id: NodeId) -> Option<usize> {
+        self.slots[id]
+    }
+}
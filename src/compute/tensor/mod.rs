@@ -1,19 +1,39 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::fmt;
+use serde::{Deserialize, Serialize};
 
+pub mod autodiff;
+pub mod display;
+pub mod graph;
+pub mod input;
+pub mod memory;
 pub mod operation;
+pub mod quantized_tensor;
+pub mod serialization;
+pub mod simplify;
+#[cfg(feature = "spirv")]
+pub mod spirv;
 pub mod supported_datatypes;
 pub mod tensor;
 pub mod tensor_binding;
 pub mod tensor_holder;
 pub mod tensor_operation_result;
+pub mod uniforms;
+pub mod vectorize;
+pub mod wgsl;
 
 const INPUT_NAME: &str = "inv";
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum TensorError {
     ShapeError(String),
+    TypeError(String),
+    QuantizationError(String),
+    /// A kernel would need more device memory than the processor allows.
+    MemoryError(String),
+    /// The shader cache directory couldn't be read or written.
+    IoError(String),
     Unimplemented(String)
 }
 
@@ -25,11 +45,11 @@ pub enum TensorOperationResult {
     Error(TensorError)
 }
 
-pub enum TensorHolder<'a> {
-    Int(&'a Tensor<i32>),
-    UInt(&'a Tensor<u32>),
-    Float(&'a Tensor<f32>),
-    Double(&'a Tensor<f64>)
+pub enum TensorHolder {
+    Int(Tensor<i32>),
+    UInt(Tensor<u32>),
+    Float(Tensor<f32>),
+    Double(Tensor<f64>)
 }
 
 pub trait SupportedDataTypes: bytemuck::Pod + Clone {
@@ -40,35 +60,95 @@ pub trait SupportedDataTypes: bytemuck::Pod + Clone {
     fn get_tensor(res: TensorOperationResult, wanted_shape: &Shape) -> Result<Tensor<Self::BindingType>, TensorError>;
 }
 
-pub struct TensorBinding<'a> {
+pub struct TensorBinding {
     pub(crate) id: u32,
-    pub(crate) value: TensorHolder<'a>,
+    /// Set for the tensor of an `Input`, which is bound by name.
+    pub(crate) name: Option<String>,
+    pub(crate) value: TensorHolder,
     pub(crate) change: Rc<RefCell<u32>>
 }
 
 pub type Shape = Vec<usize>;
 
+/// Element types a tensor can have.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DataType {
+    Int,
+    UInt,
+    Float,
+    Double
+}
+
+/// A named placeholder for a tensor that is bound when a kernel runs.
+pub struct Input {
+    pub(crate) name: String,
+    pub(crate) value: TensorValue
+}
+
 pub struct Tensor<T> {
-    pub(crate) value: RefCell<Vec<T>>,
+    pub(crate) value: Rc<RefCell<Vec<T>>>,
     pub(crate) change: Rc<RefCell<u32>>,
+    /// Elements each of the latest changes wrote, the last one being change number `change`.
+    pub(crate) changed: Rc<RefCell<Vec<std::ops::Range<usize>>>>,
     pub(crate) shape: RefCell<Shape>,
     pub(crate) is_const: bool
 }
 
+pub trait QuantizedDataTypes: bytemuck::Pod + Clone {
+    fn min_value() -> i32;
+    fn max_value() -> i32;
+    fn from_i32(value: i32) -> Self;
+    fn to_i32(self) -> i32;
+}
+
+/// Maps real values to integers as `real = scale * (quantized - zero_point)`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Quantization {
+    PerTensor {scale: f32, zero_point: i32},
+    /// One scale and zero point for every index along `axis`.
+    PerChannel {axis: usize, scales: Vec<f32>, zero_points: Vec<i32>}
+}
+
+/// Quantized values on the host. Arithmetic on them runs on the host in integer arithmetic,
+/// they can't be part of an `Operation` or a kernel; dequantize them to use them on a device.
+pub struct QuantizedTensor<Q> {
+    pub(crate) value: Vec<Q>,
+    pub(crate) shape: Shape,
+    pub(crate) quantization: Quantization
+}
+
 pub enum KnownOperation {
     Single(SingleValueOperation),
     Dual(TwoValueOperation)
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum SingleValueOperation {
-    SquareRoot
+    SquareRoot,
+    /// Sum of all elements, producing a single value.
+    Sum,
+    /// Swaps the rows and columns of a matrix.
+    Transpose
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum TwoValueOperation {
     Add,
     Subtract,
     Multiply,
-    Divide
+    Divide,
+    /// Matrix product of a `[m, k]` and a `[k, n]` operand.
+    MatMul
+}
+
+impl fmt::Debug for SingleValueOperation {    
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SingleValueOperation::SquareRoot => f.write_str("sqrt"),
+            SingleValueOperation::Sum => f.write_str("sum"),
+            SingleValueOperation::Transpose => f.write_str("transpose")
+        }
+    }    
 }
 
 impl fmt::Debug for TwoValueOperation {    
@@ -77,13 +157,110 @@ impl fmt::Debug for TwoValueOperation {
             TwoValueOperation::Add => f.write_str("+"),
             TwoValueOperation::Subtract => f.write_str("-"),
             TwoValueOperation::Multiply => f.write_str("*"),
-            TwoValueOperation::Divide => f.write_str("/")
+            TwoValueOperation::Divide => f.write_str("/"),
+            TwoValueOperation::MatMul => f.write_str("matmul")
         }
     }    
 }
 
-pub enum Operation<'a> {    
-    Var(Box<TensorBinding<'a>>),
-    SingleOp {value: Box<Operation<'a>>, result: TensorOperationResult, op: SingleValueOperation},
-    DualOp {left: Box<Operation<'a>>, right: Box<Operation<'a>>, result: TensorOperationResult, op: TwoValueOperation},
-}
\ No newline at end of file
+pub enum Operation {    
+    Var(Box<TensorBinding>),
+    /// The same `value` for every element of `result`.
+    Constant {value: f64, result: TensorOperationResult},
+    SingleOp {value: Box<Operation>, result: TensorOperationResult, op: SingleValueOperation},
+    DualOp {left: Box<Operation>, right: Box<Operation>, result: TensorOperationResult, op: TwoValueOperation},
+}
+
+pub type NodeId = usize;
+
+pub enum Node {
+    /// Refers to the graph input with this binding id.
+    Input(u32),
+    /// The same `value` for every element of `result`.
+    Constant {value: f64, result: TensorOperationResult},
+    SingleOp {value: NodeId, result: TensorOperationResult, op: SingleValueOperation},
+    DualOp {left: NodeId, right: NodeId, result: TensorOperationResult, op: TwoValueOperation},
+}
+
+/// One or more `Operation`s lowered to a DAG: equal subexpressions share one node and every
+/// tensor is bound once, in order of first use. Nodes are stored in topological order.
+pub struct Graph {
+    pub(crate) nodes: Vec<Node>,
+    pub(crate) consumers: Vec<Vec<NodeId>>,
+    pub(crate) inputs: Vec<TensorBinding>,
+    pub(crate) outputs: Vec<NodeId>
+}
+
+/// How a graph is split into kernels and where the intermediates between them are stored,
+/// see `Graph::plan_memory`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryPlan {
+    pub(crate) stages: Vec<usize>,
+    pub(crate) stage_count: usize,
+    pub(crate) slots: Vec<Option<usize>>,
+    pub(crate) pool: Vec<u64>,
+    pub(crate) io_size: u64
+}
+
+/// The uniform buffer every kernel of a graph reads, see `Graph::plan_uniforms`: the element
+/// count and index strides of each kernel, then the value of every scalar input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UniformLayout {
+    pub(crate) binding: u32,
+    /// `[invocations, row stride, layer stride, elements]` of every kernel.
+    pub(crate) dims: Vec<[u32; 4]>,
+    /// Binding id and offset in bytes of every scalar input.
+    pub(crate) scalars: Vec<(u32, u64)>,
+    pub(crate) size: u64
+}
+
+/// An entry of the binding table of a `Graph`, one storage buffer of the generated kernel.
+/// A tensor used several times in the graph still gets a single entry, and scalars are
+/// members of the uniform buffer instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BindingInfo {
+    pub(crate) binding: u32,
+    pub(crate) name: Option<String>,
+    pub(crate) is_result: bool,
+    pub(crate) data_type: DataType,
+    pub(crate) shape: Shape
+}
+
+/// Tensors known by name when an operation is serialized or loaded again.
+#[derive(Default)]
+pub struct TensorNames {
+    pub(crate) names: Vec<(String, TensorHolder)>
+}
+
+/// An owned tensor of any supported element type.
+#[derive(Serialize, Deserialize)]
+pub enum TensorValue {
+    Int(Tensor<i32>),
+    UInt(Tensor<u32>),
+    Float(Tensor<f32>),
+    Double(Tensor<f64>)
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum SerializedInput {
+    /// Supplied through `TensorNames` when the operation is loaded.
+    Named(String),
+    Inline(TensorValue)
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum SerializedNode {
+    Input(u32),
+    /// `strength` is the element type as given by `SupportedDataTypes::strength`.
+    Constant {value: f64, strength: usize, shape: Shape},
+    SingleOp {value: NodeId, op: SingleValueOperation},
+    DualOp {left: NodeId, right: NodeId, op: TwoValueOperation}
+}
+
+/// The graph of an `Operation` in a form that can be persisted with serde.
+#[derive(Serialize, Deserialize)]
+pub struct SerializedOperation {
+    pub inputs: Vec<SerializedInput>,
+    pub nodes: Vec<SerializedNode>,
+    pub output: NodeId
+}
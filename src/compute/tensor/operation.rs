@@ -1,198 +1,200 @@
-use std::fmt::Write;
-use std::cmp;
+use std::ops::{Add, Div, Mul, Sub};
 
-use std::ops::{Add, Sub};
+use super::{Graph, Operation, Shape, SingleValueOperation, SupportedDataTypes, TensorError, TensorHolder, TensorOperationResult, Tensor, TensorBinding, TwoValueOperation};
 
-use super::{Operation, SupportedDataTypes, TensorOperationResult, Tensor, TensorBinding, TwoValueOperation, INPUT_NAME};
+impl Operation {
+    pub(crate) fn uses_double(&self) -> bool {
+        match self {
+            Operation::Var(x) => matches!(x.value, TensorHolder::Double(_)),
+            Operation::Constant {result, ..} | Operation::SingleOp {result, ..} | Operation::DualOp {result, ..} => matches!(result, TensorOperationResult::Double(_))
+        }
+    }
+}
 
-impl<'a> Operation<'a> {
-    pub(crate) fn get_last_binding(&self) -> u32 {
+impl Operation {
+    pub fn to_graph(&self) -> Graph {
+        Graph::from_operation(self)
+    }
+
+    /// Shape of the tensor this operation produces.
+    pub fn get_shape(&self) -> Shape {
         match self {
-            Operation::Var(x) => x.id,
-            Operation::DualOp {left, right, ..} => cmp::max(left.get_last_binding(), right.get_last_binding()),
-            _ => unimplemented!()
+            Operation::Var(x) => x.value.get_shape(),
+            Operation::Constant {result, ..} | Operation::SingleOp {result, ..} | Operation::DualOp {result, ..} => result.get_own_shape()
         }
     }
 
-    pub(crate) fn reset_binding_from(&mut self, start: u32) -> u32 {
+    pub(crate) fn get_strength(&self) -> usize {
         match self {
-            Operation::Var(x) => { x.id = start; return start+1 },
-            Operation::DualOp {left, right, ..} => {
-                let newStart = left.reset_binding_from(start);
-                return right.reset_binding_from(newStart)
-            },
-            _ => unimplemented!()
+            Operation::Var(x) => x.value.get_strength(),
+            Operation::Constant {result, ..} | Operation::SingleOp {result, ..} | Operation::DualOp {result, ..} => result.get_own_strength()
         }
     }
 
-    pub(crate) fn contains_tensor<T>(&self, tensor: &Tensor<T>) -> Option<TensorBinding<'a>>
-    where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+    pub(crate) fn get_result(&self) -> TensorOperationResult {
         match self {
-            Operation::Var(x) => {
-                //x. as *const _ == ref2 as *const _
-                if T::to_data_holder(tensor) == x.value {return Some(x.copy())}
-                None
-            }
-            Operation::DualOp {left, right, ..} => {
-                if let Some(x) = left.contains_tensor(tensor) {return Some(x)}
-                if let Some(x) = right.contains_tensor(tensor) {return Some(x)}
-                None
-            }
-            _ => None
+            Operation::Constant {result, ..} | Operation::DualOp {result, ..} | Operation::SingleOp {result, ..} => result.copy(),
+            Operation::Var(_) => TensorOperationResult::Error(TensorError::Unimplemented("A single tensor has no operation result".to_string()))
         }
     }
-}
 
-impl<'a> Operation<'a> {
-    pub(crate) fn build_gpu(&self) -> (Vec<u8>, Vec<TensorBinding<'a>>, u32) {        
-        let mut inputs = Vec::<TensorBinding<'a>>::new();
-        self.fill_input(&mut inputs);
-        
-        let mut s = String::new();
-        writeln!(&mut s, "#version 450");
-        writeln!(&mut s, "layout(local_size_x = 1) in;");
-        
-        writeln!(&mut s);
-        
-        let mut binding: u32 = self.get_last_binding() +1;
-
-        for i in &inputs {
-            writeln!(&mut s, "readonly layout(set = 0, binding = {}) buffer b{} {{", (*i).id, (*i).id);
-            writeln!(&mut s, "{} {}{};", (*i).get_type_glsl(), INPUT_NAME, (*i).id);
-            writeln!(&mut s, "}};");
-            writeln!(&mut s);
+    /// The error of this operation, which every operation built on it reports unchanged.
+    pub(crate) fn get_error(&self) -> Option<TensorError> {
+        match self {
+            Operation::Constant {result: TensorOperationResult::Error(x), ..}
+            | Operation::SingleOp {result: TensorOperationResult::Error(x), ..}
+            | Operation::DualOp {result: TensorOperationResult::Error(x), ..} => Some(x.clone()),
+            _ => None
         }
+    }
 
-        writeln!(&mut s, "layout(set = 0, binding = {}) buffer b{} {{", binding, binding);
-        writeln!(&mut s, "float[] result;");
-        writeln!(&mut s, "}};");
-        writeln!(&mut s);
-        
-        writeln!(&mut s, "void main() {{");
-        writeln!(&mut s, "uint index = gl_GlobalInvocationID.x;");
-        writeln!(&mut s, "result[index] = {};", self.build_equation());
-        writeln!(&mut s, "}}");
+    pub fn sqrt(self) -> Operation {
+        Operation::single(self, SingleValueOperation::SquareRoot)
+    }
 
-        println!("{}", s);
+    /// Sums all elements into a single value.
+    pub fn sum(self) -> Operation {
+        Operation::single(self, SingleValueOperation::Sum)
+    }
 
-        (Operation::build_shader(&s), inputs, binding)
+    /// Swaps the rows and columns of a matrix.
+    pub fn transpose(self) -> Operation {
+        Operation::single(self, SingleValueOperation::Transpose)
     }
 
-    fn build_equation(&self) -> String {
-        match self {
-            Operation::Var(x) => {
-                x.get_value_glsl("index")
-            }
-            Operation::DualOp {left, right, result, op} => {
-                format!("({} {:?} {})", left.build_equation(), op, right.build_equation())
-            }
-            _ => "".to_owned()
-        }        
+    /// Matrix product of this `[m, k]` and an `[k, n]` operation.
+    pub fn matmul(self, other: Operation) -> Operation {
+        Operation::dual(self, other, TwoValueOperation::MatMul)
     }
 
-    fn fill_input(&self, inputs: &mut Vec<TensorBinding<'a>>) {
+    pub(crate) fn single(value: Operation, op: SingleValueOperation) -> Operation {
+        let result = match value.get_error() {
+            Some(x) => TensorOperationResult::Error(x),
+            None => TensorOperationResult::from_single(value.get_shape(), value.get_strength(), op)
+        };
+        Operation::SingleOp {value: Box::new(value), result, op}
+    }
+
+    pub(crate) fn dual(left: Operation, right: Operation, op: TwoValueOperation) -> Operation {
+        let result = TensorOperationResult::from_2_ops(&left, &right, op);
+        Operation::DualOp {left: Box::new(left), right: Box::new(right), result, op}
+    }
+
+    pub(crate) fn constant(value: f64, shape: Shape, strength: usize) -> Operation {
+        Operation::Constant {value, result: TensorOperationResult::with_strength(shape, strength)}
+    }
+}
+
+impl<'a, T> From<&'a Tensor<T>> for Operation
+where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+    fn from(tensor: &'a Tensor<T>) -> Operation {
+        Operation::Var(Box::new(TensorBinding::from_tensor(tensor)))
+    }
+}
+
+impl SingleValueOperation {
+    pub(crate) fn apply_f64(&self, value: f64) -> f64 {
         match self {
-            Operation::Var(x) => {
-                if !inputs.iter().any(|y| y.id == x.id) {
-                    inputs.push(x.copy());
-                }
-            },
-            Operation::SingleOp {value, result, op}  => {
-                value.fill_input(inputs);
-            },
-            Operation::DualOp {left, right, result, op} => {
-                left.fill_input(inputs);
-                right.fill_input(inputs);
-            }
+            SingleValueOperation::SquareRoot => value.sqrt(),
+            SingleValueOperation::Transpose => value,
+            SingleValueOperation::Sum => unreachable!("a sum depends on every element of its operand")
         }
     }
+}
 
-    fn build_shader(src: &str) -> Vec<u8> {
-        let mut compiler = shaderc::Compiler::new().unwrap();
-        let binary_result = compiler.compile_into_spirv(
-            src, shaderc::ShaderKind::Compute,
-            "shader.glsl", "main", None).unwrap();
+impl TwoValueOperation {
+    pub(crate) fn apply_f64(&self, left: f64, right: f64) -> f64 {
+        match self {
+            TwoValueOperation::Add => left + right,
+            TwoValueOperation::Subtract => left - right,
+            TwoValueOperation::Multiply => left * right,
+            TwoValueOperation::Divide => left / right,
+            TwoValueOperation::MatMul => unreachable!("a matrix product depends on a row and a column of its operands")
+        }
+    }
+}
 
-        binary_result.as_binary_u8().to_owned()
+impl Clone for Operation {
+    fn clone(&self) -> Self {
+        match self {
+            Operation::Var(x) => Operation::Var(Box::new(x.copy())),
+            Operation::Constant {value, result} => Operation::Constant {value: *value, result: result.copy()},
+            Operation::SingleOp {value, result, op} => Operation::SingleOp {value: value.clone(), result: result.copy(), op: *op},
+            Operation::DualOp {left, right, result, op} => Operation::DualOp {left: left.clone(), right: right.clone(), result: result.copy(), op: *op}
+        }
     }
 }
 
-impl<'a, T> Add<&'a Tensor<T>> for Operation<'a>
+impl<'a, T> Add<&'a Tensor<T>> for Operation
 where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
-    type Output = Operation<'a>;
+    type Output = Operation;
 
-    fn add(self, tensor: &'a Tensor<T>) -> Operation<'a> {
-        let result = TensorOperationResult::from_1_and_op(tensor, &self, TwoValueOperation::Add);
-        let binding = self.get_last_binding() + 1;
+    fn add(self, tensor: &'a Tensor<T>) -> Operation {
+        Operation::dual(self, Operation::from(tensor), TwoValueOperation::Add)
+    }
+}
 
-        let tensor = match self.contains_tensor(tensor) {
-            Some(x) => x.copy(),
-            None => TensorBinding::from_tensor(tensor, binding)
-        };
+impl Add<Operation> for Operation {
+    type Output = Operation;
 
-        Operation::DualOp {
-            left: Box::new(self),
-            right: Box::new(Operation::Var(Box::new(tensor))), 
-            result,
-            op: TwoValueOperation::Add
-        }
+    fn add(self, op: Operation) -> Operation {
+        Operation::dual(self, op, TwoValueOperation::Add)
     }
 }
 
-impl<'a> Add<Operation<'a>> for Operation<'a> {
-    type Output = Operation<'a>;
 
-    fn add(self, mut op: Operation<'a>) -> Operation<'a> {
-        let result = TensorOperationResult::from_2_ops(&op, &self, TwoValueOperation::Add);
-        let binding = self.get_last_binding();
-        op.reset_binding_from(binding+1);
 
-        Operation::DualOp {
-            left: Box::new(self), 
-            right: Box::new(op),
-            result,
-            op: TwoValueOperation::Add
-        }
+impl<'a, T> Sub<&'a Tensor<T>> for Operation
+where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+    type Output = Operation;
+
+    fn sub(self, tensor: &'a Tensor<T>) -> Operation {
+        Operation::dual(self, Operation::from(tensor), TwoValueOperation::Subtract)
+    }
+}
+
+impl Sub<Operation> for Operation {
+    type Output = Operation;
+
+    fn sub(self, op: Operation) -> Operation {
+        Operation::dual(self, op, TwoValueOperation::Subtract)
     }
 }
 
 
 
-impl<'a, T> Sub<&'a Tensor<T>> for Operation<'a>
+impl<'a, T> Mul<&'a Tensor<T>> for Operation
 where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
-    type Output = Operation<'a>;
+    type Output = Operation;
 
-    fn sub(self, tensor: &'a Tensor<T>) -> Operation<'a> {
-        let result = TensorOperationResult::from_1_and_op(tensor, &self, TwoValueOperation::Subtract);
-        let binding = self.get_last_binding() + 1;
+    fn mul(self, tensor: &'a Tensor<T>) -> Operation {
+        Operation::dual(self, Operation::from(tensor), TwoValueOperation::Multiply)
+    }
+}
 
-        let tensor = match self.contains_tensor(tensor) {
-            Some(x) => x.copy(),
-            None => TensorBinding::from_tensor(tensor, binding)
-        };
+impl Mul<Operation> for Operation {
+    type Output = Operation;
 
-        Operation::DualOp {
-            left:  Box::new(self),
-            right: Box::new(Operation::Var(Box::new(tensor))),
-            result,
-            op: TwoValueOperation::Subtract
-        }
+    fn mul(self, op: Operation) -> Operation {
+        Operation::dual(self, op, TwoValueOperation::Multiply)
     }
 }
 
-impl<'a> Sub<Operation<'a>> for Operation<'a> {
-    type Output = Operation<'a>;
 
-    fn sub(self, mut op: Operation<'a>) -> Operation<'a> {
-        let result = TensorOperationResult::from_2_ops(&op, &self, TwoValueOperation::Subtract);
-        let binding = self.get_last_binding();
-        op.reset_binding_from(binding+1);
 
-        Operation::DualOp {
-            left: Box::new(self), 
-            right: Box::new(op),
-            result,
-            op: TwoValueOperation::Subtract
-        }
+impl<'a, T> Div<&'a Tensor<T>> for Operation
+where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+    type Output = Operation;
+
+    fn div(self, tensor: &'a Tensor<T>) -> Operation {
+        Operation::dual(self, Operation::from(tensor), TwoValueOperation::Divide)
     }
-}
\ No newline at end of file
+}
+
+impl Div<Operation> for Operation {
+    type Output = Operation;
+
+    fn div(self, op: Operation) -> Operation {
+        Operation::dual(self, op, TwoValueOperation::Divide)
+    }
+}
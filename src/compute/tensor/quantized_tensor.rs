@@ -0,0 +1,180 @@
+use super::{Quantization, QuantizedDataTypes, QuantizedTensor, Shape, Tensor, TensorError};
+
+impl Quantization {
+    /// Per tensor parameters covering the real range `[min, max]` with the full range of `Q`.
+    pub fn from_range<Q: QuantizedDataTypes>(min: f32, max: f32) -> Quantization {
+        let min = min.min(0.0);
+        let max = max.max(0.0);
+        let steps = (Q::max_value() - Q::min_value()) as f32;
+        let scale = if max > min {(max - min) / steps} else {1.0};
+        let zero_point = Q::min_value() - (min / scale).round() as i32;
+
+        Quantization::PerTensor {scale, zero_point: zero_point.max(Q::min_value()).min(Q::max_value())}
+    }
+
+    fn validate(&self, shape: &Shape) -> Result<(), TensorError> {
+        match self {
+            Quantization::PerTensor {scale, ..} => {
+                if *scale <= 0.0 {return Err(TensorError::QuantizationError("Scale has to be positive".to_string()))}
+            },
+            Quantization::PerChannel {axis, scales, zero_points} => {
+                if *axis >= shape.len() {
+                    return Err(TensorError::QuantizationError(format!("Channel axis {} out of bounds for shape {:?}", axis, shape)))
+                }
+                if scales.len() != shape[*axis] || zero_points.len() != shape[*axis] {
+                    return Err(TensorError::QuantizationError(
+                        format!("Axis {} has {} channels, but got {} scales and {} zero points", axis, shape[*axis], scales.len(), zero_points.len())
+                    ))
+                }
+                if scales.iter().any(|x| *x <= 0.0) {return Err(TensorError::QuantizationError("Scale has to be positive".to_string()))}
+            }
+        }
+        Ok(())
+    }
+
+    /// Scale and zero point used for the element at flat `index` of a tensor with `shape`.
+    fn params_at(&self, shape: &Shape, index: usize) -> (f32, i32) {
+        match self {
+            Quantization::PerTensor {scale, zero_point} => (*scale, *zero_point),
+            Quantization::PerChannel {axis, scales, zero_points} => {
+                let stride: usize = shape[axis+1..].iter().product();
+                let channel = (index / stride) % shape[*axis];
+                (scales[channel], zero_points[channel])
+            }
+        }
+    }
+
+    /// Parameters of a matrix along `axis`, with per channel parameters only allowed on `axis`.
+    fn matrix_params(&self, axis: usize, len: usize) -> Result<(Vec<f32>, Vec<i32>), TensorError> {
+        match self {
+            Quantization::PerTensor {scale, zero_point} => Ok((vec![*scale; len], vec![*zero_point; len])),
+            Quantization::PerChannel {axis: a, scales, zero_points} if *a == axis => Ok((scales.to_vec(), zero_points.to_vec())),
+            Quantization::PerChannel {axis: a, ..} => Err(TensorError::QuantizationError(
+                format!("Matmul can't use per channel parameters along the reduced axis {}", a)
+            ))
+        }
+    }
+}
+
+impl<Q> QuantizedTensor<Q>
+where Q: QuantizedDataTypes {
+    pub fn with_shape(vec: Vec<Q>, shape: Shape, quantization: Quantization) -> Result<QuantizedTensor<Q>, TensorError> {
+        let length: usize = shape.iter().product();
+        if vec.len() != length {
+            return Err(TensorError::ShapeError(format!("Shape {:?} requires {} elements, but got {}", shape, length, vec.len())))
+        }
+        quantization.validate(&shape)?;
+
+        Ok(QuantizedTensor {value: vec, shape, quantization})
+    }
+
+    pub fn quantize(tensor: &Tensor<f32>, quantization: Quantization) -> Result<QuantizedTensor<Q>, TensorError> {
+        let shape = tensor.get_shape().to_vec();
+        quantization.validate(&shape)?;
+
+        let value = tensor.get_value().iter().enumerate().map(|(i, x)| {
+            let (scale, zero_point) = quantization.params_at(&shape, i);
+            requantize(*x, scale, zero_point)
+        }).collect();
+
+        QuantizedTensor::with_shape(value, shape, quantization)
+    }
+
+    pub fn dequantize(&self) -> Tensor<f32> {
+        let value = self.get_value().iter().enumerate().map(|(i, x)| {
+            let (scale, zero_point) = self.quantization.params_at(&self.shape, i);
+            scale * (x.to_i32() - zero_point) as f32
+        }).collect();
+
+        Tensor::with_shape(value, self.shape.to_vec())
+    }
+
+    pub fn get_value(&self) -> &[Q] {
+        &self.value
+    }
+
+    pub fn get_shape(&self) -> &Shape {
+        &self.shape
+    }
+
+    pub fn get_quantization(&self) -> &Quantization {
+        &self.quantization
+    }
+
+    /// Elementwise sum of the dequantized values, requantized with `quantization`. Both sides are
+    /// rescaled to the scale of the result with fixed point multipliers and added as integers.
+    pub fn add(&self, other: &QuantizedTensor<Q>, quantization: Quantization) -> Result<QuantizedTensor<Q>, TensorError> {
+        if self.shape != other.shape {
+            return Err(TensorError::ShapeError(format!("Add shapes not matching: {:?} and {:?}", self.shape, other.shape)))
+        }
+        quantization.validate(&self.shape)?;
+
+        let (a, b) = (&self.value, &other.value);
+
+        let value = (0..a.len()).map(|i| {
+            let (scale_a, zero_a) = self.quantization.params_at(&self.shape, i);
+            let (scale_b, zero_b) = other.quantization.params_at(&other.shape, i);
+            let (scale, zero_point) = quantization.params_at(&self.shape, i);
+
+            let acc_a: i32 = a[i].to_i32() - zero_a;
+            let acc_b: i32 = b[i].to_i32() - zero_b;
+            let sum = (acc_a as i64).saturating_mul(get_multiplier(scale_a / scale))
+                .saturating_add((acc_b as i64).saturating_mul(get_multiplier(scale_b / scale)));
+            // rounds half up, the shift of a negative sum rounds towards negative infinity
+            let quantized = sum.saturating_add(1 << (RESCALE_BITS - 1)) >> RESCALE_BITS;
+            saturate(quantized.saturating_add(zero_point as i64))
+        }).collect();
+
+        QuantizedTensor::with_shape(value, self.shape.to_vec(), quantization)
+    }
+
+    /// Matrix product of a `[m, k]` and a `[k, n]` tensor, accumulated in `i64` and requantized with `quantization`.
+    /// Per channel parameters are supported along the rows of `self` and the columns of `other`.
+    pub fn matmul(&self, other: &QuantizedTensor<Q>, quantization: Quantization) -> Result<QuantizedTensor<Q>, TensorError> {
+        if self.shape.len() != 2 || other.shape.len() != 2 || self.shape[1] != other.shape[0] {
+            return Err(TensorError::ShapeError(format!("Matmul shapes not matching: {:?} and {:?}", self.shape, other.shape)))
+        }
+
+        let (m, k, n) = (self.shape[0], self.shape[1], other.shape[1]);
+        let shape = vec![m, n];
+        quantization.validate(&shape)?;
+
+        let (scales_a, zeros_a) = self.quantization.matrix_params(0, m)?;
+        let (scales_b, zeros_b) = other.quantization.matrix_params(1, n)?;
+
+        let (a, b) = (&self.value, &other.value);
+
+        let mut value = Vec::<Q>::with_capacity(m * n);
+        for row in 0..m {
+            for col in 0..n {
+                let mut acc: i64 = 0;
+                for i in 0..k {
+                    acc += (a[row * k + i].to_i32() as i64 - zeros_a[row] as i64) * (b[i * n + col].to_i32() as i64 - zeros_b[col] as i64);
+                }
+
+                let (scale, zero_point) = quantization.params_at(&shape, row * n + col);
+                let real = scales_a[row] * scales_b[col] * acc as f32;
+                value.push(requantize(real, scale, zero_point));
+            }
+        }
+
+        QuantizedTensor::with_shape(value, shape, quantization)
+    }
+}
+
+/// Fractional bits of the fixed point multipliers `QuantizedTensor::add` rescales with.
+const RESCALE_BITS: u32 = 24;
+
+/// `ratio` of two scales as a fixed point multiplier with `RESCALE_BITS` fractional bits.
+fn get_multiplier(ratio: f32) -> i64 {
+    (ratio as f64 * (1u64 << RESCALE_BITS) as f64).round() as i64
+}
+
+/// `real` quantized with `scale` and `zero_point`, saturated to the range of `Q`.
+fn requantize<Q: QuantizedDataTypes>(real: f32, scale: f32, zero_point: i32) -> Q {
+    saturate(((real / scale).round() as i64).saturating_add(zero_point as i64))
+}
+
+fn saturate<Q: QuantizedDataTypes>(quantized: i64) -> Q {
+    Q::from_i32(quantized.max(Q::min_value() as i64).min(Q::max_value() as i64) as i32)
+}
@@ -0,0 +1,146 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error;
+
+use super::{Node, NodeId, Operation, SerializedInput, SerializedNode, SerializedOperation, Shape, SupportedDataTypes, Tensor, TensorBinding, TensorError, TensorHolder, TensorNames, TensorValue};
+
+#[derive(Serialize)]
+struct TensorDataRef<'b, T> {
+    shape: &'b [usize],
+    data: &'b [T],
+    is_const: bool
+}
+
+#[derive(Deserialize)]
+struct TensorData<T> {
+    shape: Shape,
+    data: Vec<T>,
+    #[serde(default)]
+    is_const: bool
+}
+
+impl<T> Serialize for Tensor<T>
+where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> + Serialize {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        TensorDataRef {shape: &*self.get_shape(), data: &*self.get_value(), is_const: self.is_const}.serialize(serializer)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Tensor<T>
+where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> + Deserialize<'de> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data = TensorData::<T>::deserialize(deserializer)?;
+
+        let length: usize = data.shape.iter().product();
+        if length != data.data.len() {
+            return Err(D::Error::custom(format!("Shape {:?} requires {} elements, but got {}", data.shape, length, data.data.len())))
+        }
+
+        Ok(Tensor {value: Rc::new(RefCell::new(data.data)), change: Rc::new(RefCell::new(0)), changed: Rc::new(RefCell::new(Vec::new())), shape: RefCell::new(data.shape), is_const: data.is_const})
+    }
+}
+
+impl TensorNames {
+    pub fn new() -> TensorNames {
+        TensorNames::default()
+    }
+
+    pub fn insert<T>(&mut self, name: &str, tensor: &Tensor<T>)
+    where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+        self.names.retain(|(x, _)| x != name);
+        self.names.push((name.to_string(), T::to_data_holder(tensor)));
+    }
+
+    fn get_name(&self, tensor: &TensorHolder) -> Option<&str> {
+        self.names.iter().find(|(_, x)| x == tensor).map(|(x, _)| x.as_str())
+    }
+
+    fn get_tensor(&self, name: &str) -> Option<TensorHolder> {
+        self.names.iter().find(|(x, _)| x == name).map(|(_, x)| x.copy())
+    }
+}
+
+impl TensorValue {
+    fn from_holder(tensor: &TensorHolder) -> TensorValue {
+        match tensor {
+            TensorHolder::Int(x) => TensorValue::Int(Tensor {is_const: x.is_const, ..x.copy()}),
+            TensorHolder::UInt(x) => TensorValue::UInt(Tensor {is_const: x.is_const, ..x.copy()}),
+            TensorHolder::Float(x) => TensorValue::Float(Tensor {is_const: x.is_const, ..x.copy()}),
+            TensorHolder::Double(x) => TensorValue::Double(Tensor {is_const: x.is_const, ..x.copy()})
+        }
+    }
+
+    pub(crate) fn get_holder(&self) -> TensorHolder {
+        match self {
+            TensorValue::Int(x) => TensorHolder::Int(x.share()),
+            TensorValue::UInt(x) => TensorHolder::UInt(x.share()),
+            TensorValue::Float(x) => TensorHolder::Float(x.share()),
+            TensorValue::Double(x) => TensorHolder::Double(x.share())
+        }
+    }
+}
+
+impl SerializedOperation {
+    /// Captures the graph of `op`. Tensors registered in `names` and `Input`s are stored
+    /// by name, all others are inlined with their current value.
+    pub fn from_operation(op: &Operation, names: &TensorNames) -> SerializedOperation {
+        let graph = op.to_graph();
+
+        let inputs = graph.inputs.iter().map(|x| {
+            match names.get_name(&x.value).or(x.name.as_deref()) {
+                Some(name) => SerializedInput::Named(name.to_string()),
+                None => SerializedInput::Inline(TensorValue::from_holder(&x.value))
+            }
+        }).collect();
+
+        let nodes = graph.nodes.iter().map(|x| {
+            match x {
+                Node::Input(x) => SerializedNode::Input(*x),
+                Node::SingleOp {value, op, ..} => SerializedNode::SingleOp {value: *value, op: *op},
+                Node::DualOp {left, right, op, ..} => SerializedNode::DualOp {left: *left, right: *right, op: *op},
+                Node::Constant {value, result} => SerializedNode::Constant {value: *value, strength: result.get_own_strength(), shape: result.get_own_shape()}
+            }
+        }).collect();
+
+        SerializedOperation {inputs, nodes, output: graph.outputs[0]}
+    }
+
+    /// Rebuilds the operation, binding named inputs to the tensors in `names`
+    /// and inlined inputs to the tensors owned by `self`.
+    pub fn to_operation(&self, names: &TensorNames) -> Result<Operation, TensorError> {
+        let mut built: Vec<Option<Operation>> = (0..self.nodes.len()).map(|_| None).collect();
+
+        for id in 0..self.nodes.len() {
+            let op = match &self.nodes[id] {
+                SerializedNode::Input(x) => {
+                    let tensor = match self.inputs.get(*x as usize) {
+                        Some(SerializedInput::Named(name)) => names.get_tensor(name)
+                            .ok_or_else(|| TensorError::Unimplemented(format!("No tensor named {}", name)))?,
+                        Some(SerializedInput::Inline(value)) => value.get_holder(),
+                        None => return Err(TensorError::Unimplemented(format!("Input {} doesn't exist", x)))
+                    };
+                    Operation::Var(Box::new(TensorBinding::from_holder(tensor)))
+                },
+                SerializedNode::Constant {value, strength, shape} => Operation::constant(*value, shape.to_vec(), *strength),
+                SerializedNode::SingleOp {value, op} => Operation::single(Self::get_built(&built, *value, id)?, *op),
+                SerializedNode::DualOp {left, right, op} => {
+                    let left = Self::get_built(&built, *left, id)?;
+                    let right = Self::get_built(&built, *right, id)?;
+                    Operation::dual(left, right, *op)
+                }
+            };
+            built[id] = Some(op);
+        }
+
+        Self::get_built(&built, self.output, self.nodes.len())
+    }
+
+    /// Operands have to be defined before the node at `user` that refers to them.
+    fn get_built(built: &[Option<Operation>], id: NodeId, user: NodeId) -> Result<Operation, TensorError> {
+        match built.get(id) {
+            Some(Some(x)) if id < user => Ok(x.clone()),
+            _ => Err(TensorError::Unimplemented(format!("Node {} refers to undefined node {}", user, id)))
+        }
+    }
+}
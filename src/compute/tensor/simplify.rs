@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+use super::{Graph, Node, NodeId, SingleValueOperation, TensorBinding, TensorOperationResult, TwoValueOperation};
+use super::graph::NodeKey;
+
+impl Graph {
+    /// Returns a rewritten graph with constant subexpressions folded, trivial arithmetic
+    /// like `x + 0` or `x * 1` removed and chains of constants reassociated.
+    ///
+    /// Constants are tensors created with `Tensor::constant`, operations on constant arrays are
+    /// folded into a new constant tensor. With `strict` set,
+    /// floating point nodes are only rewritten where the result is exact for NaN,
+    /// infinities and signed zeros, so `x * 0`, `x - x`, `x + 0` and reassociation are skipped.
+    pub fn simplify(&self, strict: bool) -> Graph {
+        let mut graph = Graph::empty();
+        graph.inputs = self.inputs.iter().map(|x| x.copy()).collect();
+
+        let mut known = HashMap::<NodeKey, NodeId>::new();
+        let mut mapping = Vec::<NodeId>::with_capacity(self.nodes.len());
+
+        for node in &self.nodes {
+            let id = match node {
+                Node::Input(x) => graph.insert(NodeKey::Input(*x), Node::Input(*x), &[], &mut known),
+                Node::Constant {value, result} => graph.insert_constant(*value, result, &mut known),
+                Node::SingleOp {value, result, op} => graph.simplify_single(mapping[*value], result, *op, &mut known),
+                Node::DualOp {left, right, result, op} => graph.simplify_dual(mapping[*left], mapping[*right], result, *op, strict, &mut known)
+            };
+            mapping.push(id);
+        }
+
+        graph.outputs = self.outputs.iter().map(|x| mapping[*x]).collect();
+        graph.compact()
+    }
+
+    fn simplify_single(&mut self, value: NodeId, result: &TensorOperationResult, op: SingleValueOperation, known: &mut HashMap<NodeKey, NodeId>) -> NodeId {
+        if let Some(c) = self.get_constant(value) {
+            let folded = match op {
+                SingleValueOperation::Sum => c * self.get_node_shape(value).iter().product::<usize>() as f64,
+                _ => op.apply_f64(c)
+            };
+            let folded = result.cast_f64(folded);
+            if folded.is_finite() {return self.insert_constant(folded, result, known)}
+        }
+
+        let id = self.insert(NodeKey::Single(op, value), Node::SingleOp {value, result: result.copy(), op}, &[value], known);
+        self.fold_array(id, result, known)
+    }
+
+    fn simplify_dual(&mut self, left: NodeId, right: NodeId, result: &TensorOperationResult, op: TwoValueOperation, strict: bool, known: &mut HashMap<NodeKey, NodeId>) -> NodeId {
+        // integer arithmetic has no NaN or signed zeros, every rewrite is exact
+        let exact = !strict || result.get_own_strength() < 2;
+        let c_left = self.get_constant(left);
+        let c_right = self.get_constant(right);
+
+        if let (Some(l), Some(r)) = (c_left, c_right) {
+            let folded = match op {
+                TwoValueOperation::MatMul => l * r * self.get_matrix_shape(right).0 as f64,
+                _ => op.apply_f64(l, r)
+            };
+            let folded = result.cast_f64(folded);
+            if folded.is_finite() {return self.insert_constant(folded, result, known)}
+        }
+
+        match op {
+            TwoValueOperation::Add => {
+                if exact && c_right == Some(0.0) && self.can_replace(left, result) {return left}
+                if exact && c_left == Some(0.0) && self.can_replace(right, result) {return right}
+            },
+            TwoValueOperation::Subtract => {
+                if c_right.is_some_and(|c| c == 0.0 && c.is_sign_positive()) && self.can_replace(left, result) {return left}
+                if exact && left == right {return self.insert_constant(0.0, result, known)}
+            },
+            TwoValueOperation::Multiply => {
+                if c_right == Some(1.0) && self.can_replace(left, result) {return left}
+                if c_left == Some(1.0) && self.can_replace(right, result) {return right}
+                if exact && (c_left == Some(0.0) || c_right == Some(0.0)) {return self.insert_constant(0.0, result, known)}
+            },
+            TwoValueOperation::Divide => {
+                if c_right == Some(1.0) && self.can_replace(left, result) {return left}
+            },
+            TwoValueOperation::MatMul => {}
+        }
+
+        if exact && (op == TwoValueOperation::Add || op == TwoValueOperation::Multiply) {
+            if let Some(id) = self.reassociate(left, right, result, op, strict, known) {
+                return id
+            }
+        }
+
+        let id = self.insert(NodeKey::dual(op, left, right), Node::DualOp {left, right, result: result.copy(), op}, &[left, right], known);
+        self.fold_array(id, result, known)
+    }
+
+    /// Replaces node `id` with a constant tensor if all its operands are known at build time.
+    /// The node itself is left for `compact` to drop.
+    fn fold_array(&mut self, id: NodeId, result: &TensorOperationResult, known: &mut HashMap<NodeKey, NodeId>) -> NodeId {
+        if !self.get_operands(id).iter().all(|x| self.is_constant(*x)) {
+            return id
+        }
+
+        let operands: Vec<Vec<f64>> = self.get_operands(id).iter().map(|x| self.evaluate_node_host(*x, &[])).collect();
+        let operands: Vec<&[f64]> = operands.iter().map(|x| x.as_slice()).collect();
+        let values: Vec<f64> = self.evaluate_node_host(id, &operands).into_iter().map(|x| result.cast_f64(x)).collect();
+        if !values.iter().all(|x| x.is_finite()) {
+            return id
+        }
+        if values.len() == 1 {
+            return self.insert_constant(values[0], result, known)
+        }
+
+        match result.constant_from_f64(&values) {
+            Some(value) => {
+                let binding = self.add_input(&TensorBinding::from_holder(value));
+                self.insert(NodeKey::Input(binding), Node::Input(binding), &[], known)
+            },
+            None => id
+        }
+    }
+
+    /// Whether every element of node `id` is known at build time.
+    fn is_constant(&self, id: NodeId) -> bool {
+        match &self.nodes[id] {
+            Node::Constant {..} => true,
+            Node::Input(x) => self.inputs[*x as usize].value.is_const(),
+            _ => false
+        }
+    }
+
+    /// Rewrites `(x op c1) op c2` into `x op (c1 op c2)` for an associative `op`.
+    fn reassociate(&mut self, left: NodeId, right: NodeId, result: &TensorOperationResult, op: TwoValueOperation, strict: bool, known: &mut HashMap<NodeKey, NodeId>) -> Option<NodeId> {
+        let (inner, c2) = match (self.get_constant(left), self.get_constant(right)) {
+            (None, Some(c)) => (left, c),
+            (Some(c), None) => (right, c),
+            _ => return None
+        };
+
+        let (x, c1) = match &self.nodes[inner] {
+            Node::DualOp {left, right, op: inner_op, ..} if *inner_op == op && self.can_replace(inner, result) => {
+                match (self.get_constant(*left), self.get_constant(*right)) {
+                    (None, Some(c)) => (*left, c),
+                    (Some(c), None) => (*right, c),
+                    _ => return None
+                }
+            },
+            _ => return None
+        };
+
+        let folded = result.cast_f64(op.apply_f64(c1, c2));
+        if !folded.is_finite() {return None}
+
+        let constant = self.insert_constant(folded, result, known);
+        Some(self.simplify_dual(x, constant, result, op, strict, known))
+    }
+
+    fn insert_constant(&mut self, value: f64, result: &TensorOperationResult, known: &mut HashMap<NodeKey, NodeId>) -> NodeId {
+        let key = NodeKey::Constant(value.to_bits(), result.get_own_strength(), result.get_own_shape());
+        self.insert(key, Node::Constant {value, result: result.copy()}, &[], known)
+    }
+
+    /// The value of a node that is the same for every element, if it is known at build time.
+    fn get_constant(&self, id: NodeId) -> Option<f64> {
+        match &self.nodes[id] {
+            Node::Constant {value, ..} => Some(*value),
+            Node::Input(x) => {
+                let input = &self.inputs[*x as usize].value;
+                if input.is_const() && input.is_single() {Some(input.get_f64(0))} else {None}
+            },
+            _ => None
+        }
+    }
+
+    /// Whether node `id` produces exactly the element type and shape of `result`.
+    fn can_replace(&self, id: NodeId, result: &TensorOperationResult) -> bool {
+        self.get_node_strength(id) == result.get_own_strength() && self.get_node_shape(id) == result.get_own_shape()
+    }
+
+    /// Drops nodes and inputs that don't contribute to an output.
+    fn compact(self) -> Graph {
+        let mut used = vec![false; self.nodes.len()];
+        self.outputs.iter().for_each(|x| used[*x] = true);
+        for id in (0..self.nodes.len()).rev() {
+            if used[id] {
+                self.get_operands(id).into_iter().for_each(|x| used[x] = true);
+            }
+        }
+
+        let mut graph = Graph::empty();
+        let mut known = HashMap::<NodeKey, NodeId>::new();
+        let mut mapping = vec![0; self.nodes.len()];
+
+        for (id, node) in self.nodes.iter().enumerate() {
+            if !used[id] {continue}
+
+            mapping[id] = match node {
+                Node::Input(x) => {
+                    let binding = graph.add_input(&self.inputs[*x as usize]);
+                    graph.insert(NodeKey::Input(binding), Node::Input(binding), &[], &mut known)
+                },
+                Node::Constant {value, result} => graph.insert_constant(*value, result, &mut known),
+                Node::SingleOp {value, result, op} => {
+                    let value = mapping[*value];
+                    graph.insert(NodeKey::Single(*op, value), Node::SingleOp {value, result: result.copy(), op: *op}, &[value], &mut known)
+                },
+                Node::DualOp {left, right, result, op} => {
+                    let (left, right) = (mapping[*left], mapping[*right]);
+                    graph.insert(NodeKey::dual(*op, left, right), Node::DualOp {left, right, result: result.copy(), op: *op}, &[left, right], &mut known)
+                }
+            };
+        }
+
+        graph.outputs = self.outputs.iter().map(|x| mapping[*x]).collect();
+        graph
+    }
+}
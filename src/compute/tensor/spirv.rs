@@ -0,0 +1,498 @@
+use rspirv::binary::Assemble;
+use rspirv::dr::{Builder, InsertPoint, Instruction, Operand};
+use rspirv::spirv::{AddressingModel, BuiltIn, Capability, Decoration, ExecutionMode, ExecutionModel, FunctionControl, LoopControl, MemoryModel, Op, SelectionControl, StorageClass, Word};
+
+use std::collections::HashMap;
+
+use super::{Graph, MemoryPlan, Node, NodeId, SingleValueOperation, TwoValueOperation, UniformLayout};
+
+/// Square root in the GLSL.std.450 instruction set.
+const GLSL_SQRT: Word = 31;
+/// Unsigned minimum in the GLSL.std.450 instruction set.
+const GLSL_UMIN: Word = 38;
+
+impl Graph {
+    /// SPIR-V module for kernel `stage` of `plan`, emitted without a shader compiler and with
+    /// the same bindings and bounds checks as `build_glsl`.
+    pub fn build_spirv(&self, plan: &MemoryPlan, stage: usize, workgroup_size: u32) -> Vec<u8> {
+        let mut emitter = SpirvEmitter::new(self, plan, stage, workgroup_size);
+        emitter.emit();
+        let words = emitter.b.module().assemble();
+
+        bytemuck::cast_slice(&words).to_owned()
+    }
+}
+
+/// State of the module of one kernel while it is emitted.
+struct SpirvEmitter<'a> {
+    graph: &'a Graph,
+    plan: &'a MemoryPlan,
+    stage: usize,
+    workgroup_size: u32,
+    /// Elements every invocation computes, see `Graph::get_vector_width`.
+    width: usize,
+    uniforms: UniformLayout,
+    b: Builder,
+    glsl: Word,
+    /// Label of the block instructions are added to.
+    block: Word,
+    /// Invocation index, the element this invocation computes.
+    index: Word,
+    /// Variable of every binding the kernel declares.
+    buffers: HashMap<u32, Word>,
+    /// Variable of the uniform buffer.
+    parameters: Word,
+    /// Value of every node computed so far.
+    values: HashMap<NodeId, Word>,
+    /// Types that already have their layout decorations.
+    decorated: Vec<Word>
+}
+
+impl<'a> SpirvEmitter<'a> {
+    fn new(graph: &'a Graph, plan: &'a MemoryPlan, stage: usize, workgroup_size: u32) -> SpirvEmitter<'a> {
+        let mut b = Builder::new();
+        b.set_version(1, 0);
+        b.capability(Capability::Shader);
+        let glsl = b.ext_inst_import("GLSL.std.450");
+        b.memory_model(AddressingModel::Logical, MemoryModel::GLSL450);
+
+        let width = graph.get_vector_width(plan, stage);
+        let uniforms = graph.plan_uniforms(plan, workgroup_size);
+        SpirvEmitter {graph, plan, stage, workgroup_size, width, uniforms, b, glsl, block: 0, index: 0, buffers: HashMap::new(), parameters: 0, values: HashMap::new(), decorated: Vec::new()}
+    }
+
+    fn emit(&mut self) {
+        let graph = self.graph;
+        let (plan, stage) = (self.plan, self.stage);
+
+        if (0..graph.nodes.len()).any(|x| graph.get_node_strength(x) == 3) {
+            self.b.capability(Capability::Float64);
+        }
+
+        self.declare_parameters();
+        for i in graph.inputs.iter().filter(|x| !x.value.is_single()) {
+            self.declare_buffer(i.id, i.value.get_strength());
+        }
+        for (i, binding) in graph.result_bindings().into_iter().enumerate() {
+            if plan.get_stage(graph.outputs[i]) != stage {continue}
+            self.declare_buffer(binding, graph.get_node_strength(graph.outputs[i]));
+        }
+        for id in 0..graph.nodes.len() {
+            if let Some(slot) = plan.get_slot(id) {
+                if !graph.is_used_in_stage(plan, id, stage) {continue}
+                self.declare_buffer(graph.pool_binding(slot), graph.get_node_strength(id));
+            }
+        }
+
+        let uint = self.b.type_int(32, 0);
+        let uvec3 = self.b.type_vector(uint, 3);
+        let gid_pointer = self.b.type_pointer(None, StorageClass::Input, uvec3);
+        let gid = self.b.variable(gid_pointer, None, StorageClass::Input, None);
+        self.b.decorate(gid, Decoration::BuiltIn, vec![Operand::BuiltIn(BuiltIn::GlobalInvocationId)]);
+
+        let void = self.b.type_void();
+        let function_type = self.b.type_function(void, vec![]);
+        let main = self.b.begin_function(void, None, FunctionControl::NONE, function_type).unwrap();
+        self.block = self.b.begin_block(None).unwrap();
+        let invocation = self.b.load(uvec3, None, gid, None, vec![]).unwrap();
+        self.index = self.b.composite_extract(uint, None, invocation, vec![0]).unwrap();
+
+        let uvec4 = self.b.type_vector(uint, 4);
+        let size_pointer = self.b.type_pointer(None, StorageClass::Uniform, uvec4);
+        let int = self.b.type_int(32, 1);
+        let (member, element) = (self.b.constant_u32(int, 0), self.b.constant_u32(int, stage as u32));
+        let size = self.b.access_chain(size_pointer, None, self.parameters, vec![member, element]).unwrap();
+        let size = self.b.load(uvec4, None, size, None, vec![]).unwrap();
+        for dimension in 1..3 {
+            let id = self.b.composite_extract(uint, None, invocation, vec![dimension]).unwrap();
+            let stride = self.b.composite_extract(uint, None, size, vec![dimension]).unwrap();
+            let offset = self.b.i_mul(uint, None, id, stride).unwrap();
+            self.index = self.b.i_add(uint, None, self.index, offset).unwrap();
+        }
+
+        // invocations past the last element return right away
+        let boolean = self.b.type_bool();
+        let length = self.b.composite_extract(uint, None, size, vec![0]).unwrap();
+        let (body, exit) = (self.b.id(), self.b.id());
+        let condition = self.b.u_less_than(boolean, None, self.index, length).unwrap();
+        self.merge(Op::SelectionMerge, vec![Operand::IdRef(body), Operand::SelectionControl(SelectionControl::NONE)]);
+        self.b.branch_conditional(condition, body, exit, vec![]).unwrap();
+        self.b.begin_block(Some(exit)).unwrap();
+        self.b.ret().unwrap();
+        self.block = self.b.begin_block(Some(body)).unwrap();
+
+        for (id, node) in graph.nodes.iter().enumerate() {
+            if plan.get_stage(id) != stage {continue}
+            let strength = graph.get_node_strength(id);
+            let value = match node {
+                Node::Input(_) | Node::Constant {..} => continue,
+                Node::SingleOp {value, op: SingleValueOperation::Sum, ..} => self.emit_sum(*value, strength),
+                Node::SingleOp {value, op: SingleValueOperation::Transpose, ..} => {
+                    let (rows, columns) = graph.get_matrix_shape(id);
+                    let j = self.emit_clamped_index(rows * columns);
+                    let (rows, columns) = (self.b.constant_u32(uint, rows as u32), self.b.constant_u32(uint, columns as u32));
+                    let column = self.b.u_mod(uint, None, j, columns).unwrap();
+                    let row = self.b.u_div(uint, None, j, columns).unwrap();
+                    let offset = self.b.i_mul(uint, None, column, rows).unwrap();
+                    let element = self.b.i_add(uint, None, offset, row).unwrap();
+                    self.get_operand(*value, element, strength)
+                },
+                Node::DualOp {left, right, op: TwoValueOperation::MatMul, ..} => self.emit_matmul(id, *left, *right, strength),
+                Node::SingleOp {value, op: SingleValueOperation::SquareRoot, ..} => {
+                    let value = self.get_operand(*value, self.index, strength);
+                    let t = self.get_type(strength);
+                    self.b.ext_inst(t, None, self.glsl, GLSL_SQRT, vec![Operand::IdRef(value)]).unwrap()
+                },
+                Node::DualOp {left, right, op, ..} => {
+                    let left = self.get_operand(*left, self.index, strength);
+                    let right = self.get_operand(*right, self.index, strength);
+                    self.emit_dual(*op, strength, left, right)
+                }
+            };
+            self.values.insert(id, value);
+
+            if let Some(slot) = plan.get_slot(id) {
+                let length: usize = graph.get_node_shape(id).iter().product();
+                let length = self.b.constant_u32(uint, length as u32);
+                self.emit_guarded_store(graph.pool_binding(slot), strength, length, value);
+            }
+        }
+
+        if self.width > 1 {
+            let elements = self.b.composite_extract(uint, None, size, vec![3]).unwrap();
+            self.emit_vector_stores(elements);
+        }
+        for (i, binding) in graph.result_bindings().into_iter().enumerate() {
+            let output = graph.outputs[i];
+            if plan.get_stage(output) != stage || self.width > 1 {continue}
+            let strength = graph.get_node_strength(output);
+            let value = self.get_operand(output, self.index, strength);
+            let length = self.b.array_length(uint, None, self.buffers[&binding], 0).unwrap();
+            self.emit_guarded_store(binding, strength, length, value);
+        }
+
+        self.b.ret().unwrap();
+        self.b.end_function().unwrap();
+        self.b.entry_point(ExecutionModel::GLCompute, main, "main", vec![gid]);
+        self.b.execution_mode(main, ExecutionMode::LocalSize, vec![self.workgroup_size, 1, 1]);
+    }
+
+    /// Declares the uniform buffer with the dimensions of every kernel and the scalar inputs,
+    /// at the offsets of `Graph::plan_uniforms`.
+    fn declare_parameters(&mut self) {
+        let graph = self.graph;
+        let uint = self.b.type_int(32, 0);
+        let uvec4 = self.b.type_vector(uint, 4);
+        let length = self.b.constant_u32(uint, self.uniforms.dims.len() as u32);
+        let dims = self.b.type_array(uvec4, length);
+        self.b.decorate(dims, Decoration::ArrayStride, vec![Operand::LiteralInt32(16)]);
+
+        let mut members = vec![dims];
+        for (binding, _) in self.uniforms.scalars.clone() {
+            members.push(self.get_element_type(graph.inputs[binding as usize].value.get_strength()));
+        }
+
+        let block = self.b.type_struct(members);
+        self.b.decorate(block, Decoration::Block, vec![]);
+        self.b.member_decorate(block, 0, Decoration::Offset, vec![Operand::LiteralInt32(0)]);
+        for (member, (_, offset)) in self.uniforms.scalars.clone().into_iter().enumerate() {
+            self.b.member_decorate(block, member as u32 + 1, Decoration::Offset, vec![Operand::LiteralInt32(offset as u32)]);
+        }
+
+        let pointer = self.b.type_pointer(None, StorageClass::Uniform, block);
+        self.parameters = self.b.variable(pointer, None, StorageClass::Uniform, None);
+        self.b.decorate(self.parameters, Decoration::DescriptorSet, vec![Operand::LiteralInt32(0)]);
+        self.b.decorate(self.parameters, Decoration::Binding, vec![Operand::LiteralInt32(self.uniforms.binding)]);
+    }
+
+    /// Declares the storage buffer of `binding`, holding an array of values.
+    fn declare_buffer(&mut self, binding: u32, strength: usize) {
+        let element = self.get_type(strength);
+        let array = self.b.type_runtime_array(element);
+        if !self.decorated.contains(&array) {
+            let stride = if strength == 3 {8} else {4 * self.width as u32};
+            self.b.decorate(array, Decoration::ArrayStride, vec![Operand::LiteralInt32(stride)]);
+            self.decorated.push(array);
+        }
+
+        let block = self.b.type_struct(vec![array]);
+        if !self.decorated.contains(&block) {
+            self.b.decorate(block, Decoration::BufferBlock, vec![]);
+            self.b.member_decorate(block, 0, Decoration::Offset, vec![Operand::LiteralInt32(0)]);
+            self.decorated.push(block);
+        }
+
+        let pointer = self.b.type_pointer(None, StorageClass::Uniform, block);
+        let variable = self.b.variable(pointer, None, StorageClass::Uniform, None);
+        self.b.decorate(variable, Decoration::DescriptorSet, vec![Operand::LiteralInt32(0)]);
+        self.b.decorate(variable, Decoration::Binding, vec![Operand::LiteralInt32(binding)]);
+        self.buffers.insert(binding, variable);
+    }
+
+    fn get_element_type(&mut self, strength: usize) -> Word {
+        match strength {
+            0 => self.b.type_int(32, 1),
+            1 => self.b.type_int(32, 0),
+            2 => self.b.type_float(32),
+            _ => self.b.type_float(64)
+        }
+    }
+
+    /// Type of the values of a node, vectors in a vectorized kernel.
+    fn get_type(&mut self, strength: usize) -> Word {
+        let element = self.get_element_type(strength);
+        if self.width > 1 {self.b.type_vector(element, self.width as u32)} else {element}
+    }
+
+    /// `value` in every element of a vector in a vectorized kernel.
+    fn splat(&mut self, value: Word, strength: usize) -> Word {
+        if self.width == 1 {
+            return value
+        }
+        let t = self.get_type(strength);
+        self.b.composite_construct(t, None, vec![value; self.width]).unwrap()
+    }
+
+    fn get_constant(&mut self, strength: usize, value: f64) -> Word {
+        let t = self.get_element_type(strength);
+        match strength {
+            0 => self.b.constant_u32(t, value as i32 as u32),
+            1 => self.b.constant_u32(t, value as u32),
+            2 => self.b.constant_f32(t, value as f32),
+            _ => self.b.constant_f64(t, value)
+        }
+    }
+
+    /// Pointer to element `index` of the buffer of `binding`.
+    fn get_element_pointer(&mut self, binding: u32, strength: usize, index: Word) -> Word {
+        let element = self.get_type(strength);
+        let pointer = self.b.type_pointer(None, StorageClass::Uniform, element);
+        let int = self.b.type_int(32, 1);
+        let member = self.b.constant_u32(int, 0);
+        self.b.access_chain(pointer, None, self.buffers[&binding], vec![member, index]).unwrap()
+    }
+
+    fn load(&mut self, binding: u32, strength: usize, index: Word) -> Word {
+        let element = self.get_type(strength);
+        let pointer = self.get_element_pointer(binding, strength, index);
+        self.b.load(element, None, pointer, None, vec![]).unwrap()
+    }
+
+    /// Value of the scalar input bound at `binding`, read from the uniform buffer.
+    fn load_scalar(&mut self, binding: u32, strength: usize) -> Word {
+        let element = self.get_element_type(strength);
+        let pointer = self.b.type_pointer(None, StorageClass::Uniform, element);
+        let int = self.b.type_int(32, 1);
+        let member = self.uniforms.get_member(binding).unwrap();
+        let member = self.b.constant_u32(int, member);
+        let pointer = self.b.access_chain(pointer, None, self.parameters, vec![member]).unwrap();
+        self.b.load(element, None, pointer, None, vec![]).unwrap()
+    }
+
+    /// Node `id` at element `index` converted to `strength`, like `get_operand_glsl`.
+    fn get_operand(&mut self, id: NodeId, index: Word, strength: usize) -> Word {
+        let graph = self.graph;
+        let own = graph.get_node_strength(id);
+        let value = match &graph.nodes[id] {
+            Node::Input(x) if graph.inputs[*x as usize].value.is_single() => {
+                let value = self.load_scalar(*x, own);
+                self.splat(value, own)
+            },
+            Node::Input(x) => self.load(*x, own, index),
+            Node::Constant {value, ..} => {
+                let value = self.get_constant(own, *value);
+                self.splat(value, own)
+            },
+            _ if self.plan.get_stage(id) == self.stage => self.values[&id],
+            _ => {
+                let binding = graph.pool_binding(self.plan.get_slot(id).unwrap());
+                let index = if graph.get_node_shape(id).iter().product::<usize>() == 1 {self.get_constant(1, 0.0)} else {index};
+                self.load(binding, own, index)
+            }
+        };
+        self.convert(value, own, strength)
+    }
+
+    fn convert(&mut self, value: Word, from: usize, to: usize) -> Word {
+        if from == to {
+            return value
+        }
+        let t = self.get_type(to);
+        match (from, to) {
+            (0, 1) | (1, 0) => self.b.bitcast(t, None, value),
+            (0, _) => self.b.convert_s_to_f(t, None, value),
+            (1, _) => self.b.convert_u_to_f(t, None, value),
+            (_, 0) => self.b.convert_f_to_s(t, None, value),
+            (_, 1) => self.b.convert_f_to_u(t, None, value),
+            _ => self.b.f_convert(t, None, value)
+        }.unwrap()
+    }
+
+    fn emit_dual(&mut self, op: TwoValueOperation, strength: usize, left: Word, right: Word) -> Word {
+        let t = self.get_type(strength);
+        let float = strength >= 2;
+        match op {
+            TwoValueOperation::Add if float => self.b.f_add(t, None, left, right),
+            TwoValueOperation::Add => self.b.i_add(t, None, left, right),
+            TwoValueOperation::Subtract if float => self.b.f_sub(t, None, left, right),
+            TwoValueOperation::Subtract => self.b.i_sub(t, None, left, right),
+            TwoValueOperation::Multiply if float => self.b.f_mul(t, None, left, right),
+            TwoValueOperation::Multiply => self.b.i_mul(t, None, left, right),
+            TwoValueOperation::Divide if float => self.b.f_div(t, None, left, right),
+            TwoValueOperation::Divide if strength == 0 => self.b.s_div(t, None, left, right),
+            TwoValueOperation::Divide => self.b.u_div(t, None, left, right),
+            TwoValueOperation::MatMul => unreachable!("a matrix product is emitted as a loop")
+        }.unwrap()
+    }
+
+    /// The invocation index, limited to the last of `length` elements so that invocations past
+    /// the end don't read past the operands.
+    fn emit_clamped_index(&mut self, length: usize) -> Word {
+        let uint = self.b.type_int(32, 0);
+        let last = self.b.constant_u32(uint, length as u32 - 1);
+        self.b.ext_inst(uint, None, self.glsl, GLSL_UMIN, vec![Operand::IdRef(self.index), Operand::IdRef(last)]).unwrap()
+    }
+
+    /// Loop adding up the products of a row of `left` and a column of `right` for node `id`.
+    fn emit_matmul(&mut self, id: NodeId, left: NodeId, right: NodeId, strength: usize) -> Word {
+        let uint = self.b.type_int(32, 0);
+        let (m, _) = self.graph.get_matrix_shape(id);
+        let (k, n) = self.graph.get_matrix_shape(right);
+        let j = self.emit_clamped_index(m * n);
+        let (k_id, n_id) = (self.b.constant_u32(uint, k as u32), self.b.constant_u32(uint, n as u32));
+        let row = self.b.u_div(uint, None, j, n_id).unwrap();
+        let row = self.b.i_mul(uint, None, row, k_id).unwrap();
+        let column = self.b.u_mod(uint, None, j, n_id).unwrap();
+
+        self.emit_accumulate(k, strength, &mut |e: &mut Self, i| {
+            let l = e.b.i_add(uint, None, row, i).unwrap();
+            let l = e.get_operand(left, l, strength);
+            let r = e.b.i_mul(uint, None, i, n_id).unwrap();
+            let r = e.b.i_add(uint, None, r, column).unwrap();
+            let r = e.get_operand(right, r, strength);
+            e.emit_dual(TwoValueOperation::Multiply, strength, l, r)
+        })
+    }
+
+    /// Loop adding up every element of node `value`.
+    fn emit_sum(&mut self, value: NodeId, strength: usize) -> Word {
+        let length: usize = self.graph.get_node_shape(value).iter().product();
+        self.emit_accumulate(length, strength, &mut |e: &mut Self, i| e.get_operand(value, i, strength))
+    }
+
+    /// Loop adding up `element` for every counter below `length`, with the counter and the sum
+    /// as phis.
+    fn emit_accumulate(&mut self, length: usize, strength: usize, element: &mut dyn FnMut(&mut Self, Word) -> Word) -> Word {
+        let t = self.get_element_type(strength);
+        let uint = self.b.type_int(32, 0);
+        let boolean = self.b.type_bool();
+        let length = self.b.constant_u32(uint, length as u32);
+        let start = self.b.constant_u32(uint, 0);
+        let one = self.b.constant_u32(uint, 1);
+        let zero = self.get_constant(strength, 0.0);
+
+        let (header, check, body, next, merge) = (self.b.id(), self.b.id(), self.b.id(), self.b.id(), self.b.id());
+        let (i, i_next, sum, sum_next) = (self.b.id(), self.b.id(), self.b.id(), self.b.id());
+        let entry = self.block;
+        self.b.branch(header).unwrap();
+
+        self.b.begin_block(Some(header)).unwrap();
+        self.b.phi(uint, Some(i), vec![(start, entry), (i_next, next)]).unwrap();
+        self.b.phi(t, Some(sum), vec![(zero, entry), (sum_next, next)]).unwrap();
+        self.merge(Op::LoopMerge, vec![Operand::IdRef(merge), Operand::IdRef(next), Operand::LoopControl(LoopControl::NONE)]);
+        self.b.branch(check).unwrap();
+
+        self.b.begin_block(Some(check)).unwrap();
+        let condition = self.b.u_less_than(boolean, None, i, length).unwrap();
+        self.b.branch_conditional(condition, body, merge, vec![]).unwrap();
+
+        self.b.begin_block(Some(body)).unwrap();
+        let element = element(self, i);
+        let float = strength >= 2;
+        if float {self.b.f_add(t, Some(sum_next), sum, element)} else {self.b.i_add(t, Some(sum_next), sum, element)}.unwrap();
+        self.b.branch(next).unwrap();
+
+        self.b.begin_block(Some(next)).unwrap();
+        self.b.i_add(uint, Some(i_next), i, one).unwrap();
+        self.b.branch(header).unwrap();
+
+        self.block = self.b.begin_block(Some(merge)).unwrap();
+        sum
+    }
+
+    /// Stores `value` at the invocation index of the buffer of `binding` if the index is below
+    /// `length`.
+    fn emit_guarded_store(&mut self, binding: u32, strength: usize, length: Word, value: Word) {
+        let boolean = self.b.type_bool();
+        let (store, merge) = (self.b.id(), self.b.id());
+        let condition = self.b.u_less_than(boolean, None, self.index, length).unwrap();
+        self.merge(Op::SelectionMerge, vec![Operand::IdRef(merge), Operand::SelectionControl(SelectionControl::NONE)]);
+        self.b.branch_conditional(condition, store, merge, vec![]).unwrap();
+
+        self.b.begin_block(Some(store)).unwrap();
+        let pointer = self.get_element_pointer(binding, strength, self.index);
+        self.b.store(pointer, value, None, vec![]).unwrap();
+        self.b.branch(merge).unwrap();
+
+        self.block = self.b.begin_block(Some(merge)).unwrap();
+    }
+
+    /// Stores every output of a vectorized kernel, a whole vector unless the results end within
+    /// it. Then the elements before the end are stored one by one.
+    fn emit_vector_stores(&mut self, elements: Word) {
+        let graph = self.graph;
+        let uint = self.b.type_int(32, 0);
+        let boolean = self.b.type_bool();
+        let outputs: Vec<(u32, usize, Word)> = graph.result_bindings().into_iter().zip(&graph.outputs).map(|(binding, output)| {
+            let strength = graph.get_node_strength(*output);
+            (binding, strength, self.get_operand(*output, self.index, strength))
+        }).collect();
+
+        let width = self.b.constant_u32(uint, self.width as u32);
+        let first = self.b.i_mul(uint, None, self.index, width).unwrap();
+        let end = self.b.i_add(uint, None, first, width).unwrap();
+        let (whole, tail, merge) = (self.b.id(), self.b.id(), self.b.id());
+        let condition = self.b.u_less_than_equal(boolean, None, end, elements).unwrap();
+        self.merge(Op::SelectionMerge, vec![Operand::IdRef(merge), Operand::SelectionControl(SelectionControl::NONE)]);
+        self.b.branch_conditional(condition, whole, tail, vec![]).unwrap();
+
+        self.block = self.b.begin_block(Some(whole)).unwrap();
+        for (binding, strength, value) in &outputs {
+            let pointer = self.get_element_pointer(*binding, *strength, self.index);
+            self.b.store(pointer, *value, None, vec![]).unwrap();
+        }
+        self.b.branch(merge).unwrap();
+
+        self.block = self.b.begin_block(Some(tail)).unwrap();
+        let int = self.b.type_int(32, 1);
+        let member = self.b.constant_u32(int, 0);
+        for lane in 0..self.width as u32 {
+            let offset = self.b.constant_u32(uint, lane);
+            let element = self.b.i_add(uint, None, first, offset).unwrap();
+            let (store, next) = (self.b.id(), self.b.id());
+            let condition = self.b.u_less_than(boolean, None, element, elements).unwrap();
+            self.merge(Op::SelectionMerge, vec![Operand::IdRef(next), Operand::SelectionControl(SelectionControl::NONE)]);
+            self.b.branch_conditional(condition, store, next, vec![]).unwrap();
+
+            self.b.begin_block(Some(store)).unwrap();
+            let component = self.b.constant_u32(int, lane);
+            for (binding, strength, value) in &outputs {
+                let t = self.get_element_type(*strength);
+                let pointer = self.b.type_pointer(None, StorageClass::Uniform, t);
+                let pointer = self.b.access_chain(pointer, None, self.buffers[binding], vec![member, self.index, component]).unwrap();
+                let value = self.b.composite_extract(t, None, *value, vec![lane]).unwrap();
+                self.b.store(pointer, value, None, vec![]).unwrap();
+            }
+            self.b.branch(next).unwrap();
+            self.block = self.b.begin_block(Some(next)).unwrap();
+        }
+        self.b.branch(merge).unwrap();
+
+        self.block = self.b.begin_block(Some(merge)).unwrap();
+    }
+
+    /// Adds a merge instruction without ending the block, unlike `Builder::loop_merge`.
+    fn merge(&mut self, op: Op, operands: Vec<Operand>) {
+        self.b.insert_into_block(InsertPoint::End, Instruction::new(op, None, None, operands)).unwrap();
+    }
+}
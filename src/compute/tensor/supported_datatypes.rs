@@ -1,9 +1,9 @@
-use super::{SupportedDataTypes, Shape, Tensor, TensorError, TensorHolder, TensorOperationResult};
+use super::{QuantizedDataTypes, SupportedDataTypes, Shape, Tensor, TensorError, TensorHolder, TensorOperationResult};
 
 impl SupportedDataTypes for i32 {
     type BindingType = i32;
     fn to_data_holder(vec: &Tensor<Self::BindingType>) -> TensorHolder {
-        TensorHolder::Int(vec)
+        TensorHolder::Int(vec.share())
     }
     fn strength() -> usize {
         0
@@ -17,7 +17,7 @@ impl SupportedDataTypes for i32 {
                 if !x.matches_shape(wanted_shape) {return Err(TensorError::ShapeError("Result Shapes didn't match".to_owned()))}
                 Ok(Tensor::with_shape(x.get_value().to_vec(), x.get_shape().to_vec()))
             },
-            _ => Err(TensorError::Unimplemented("Type Error".to_owned()))
+            _ => Err(TensorError::TypeError("Result type didn't match".to_owned()))
         }
     }
 }
@@ -25,7 +25,7 @@ impl SupportedDataTypes for i32 {
 impl SupportedDataTypes for u32 {
     type BindingType = u32;
     fn to_data_holder(vec: &Tensor<Self::BindingType>) -> TensorHolder {
-        TensorHolder::UInt(vec)
+        TensorHolder::UInt(vec.share())
     }
     fn strength() -> usize {
         1
@@ -39,7 +39,7 @@ impl SupportedDataTypes for u32 {
                 if !x.matches_shape(wanted_shape) {return Err(TensorError::ShapeError("Result Shapes didn't match".to_owned()))}
                 Ok(Tensor::with_shape(x.get_value().to_vec(), x.get_shape().to_vec()))
             },
-            _ => Err(TensorError::Unimplemented("Type Error".to_owned()))
+            _ => Err(TensorError::TypeError("Result type didn't match".to_owned()))
         }
     }
 }
@@ -47,7 +47,7 @@ impl SupportedDataTypes for u32 {
 impl SupportedDataTypes for f32 {
     type BindingType = f32;
     fn to_data_holder(vec: &Tensor<Self::BindingType>) -> TensorHolder {
-        TensorHolder::Float(vec)
+        TensorHolder::Float(vec.share())
     }
     fn strength() -> usize {
         2
@@ -61,14 +61,14 @@ impl SupportedDataTypes for f32 {
                 if !x.matches_shape(wanted_shape) {return Err(TensorError::ShapeError("Result Shapes didn't match".to_owned()))}
                 Ok(Tensor::with_shape(x.get_value().to_vec(), x.get_shape().to_vec()))
             },
-            _ => Err(TensorError::Unimplemented("Type Error".to_owned()))
+            _ => Err(TensorError::TypeError("Result type didn't match".to_owned()))
         }
     }
 }
 impl SupportedDataTypes for f64 {
     type BindingType = f64;
     fn to_data_holder(vec: &Tensor<Self::BindingType>) -> TensorHolder {
-        TensorHolder::Double(vec)
+        TensorHolder::Double(vec.share())
     }
     fn strength() -> usize {
         3
@@ -82,7 +82,37 @@ impl SupportedDataTypes for f64 {
                 if !x.matches_shape(wanted_shape) {return Err(TensorError::ShapeError("Result Shapes didn't match".to_owned()))}
                 Ok(Tensor::with_shape(x.get_value().to_vec(), x.get_shape().to_vec()))
             },
-            _ => Err(TensorError::Unimplemented("Type Error".to_owned()))
+            _ => Err(TensorError::TypeError("Result type didn't match".to_owned()))
         }
     }
-}
\ No newline at end of file
+}
+
+impl QuantizedDataTypes for i8 {
+    fn min_value() -> i32 {
+        i8::MIN as i32
+    }
+    fn max_value() -> i32 {
+        i8::MAX as i32
+    }
+    fn from_i32(value: i32) -> Self {
+        value.max(i8::MIN as i32).min(i8::MAX as i32) as i8
+    }
+    fn to_i32(self) -> i32 {
+        self as i32
+    }
+}
+
+impl QuantizedDataTypes for u8 {
+    fn min_value() -> i32 {
+        u8::MIN as i32
+    }
+    fn max_value() -> i32 {
+        u8::MAX as i32
+    }
+    fn from_i32(value: i32) -> Self {
+        value.max(u8::MIN as i32).min(u8::MAX as i32) as u8
+    }
+    fn to_i32(self) -> i32 {
+        self as i32
+    }
+}
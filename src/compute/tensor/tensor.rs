@@ -1,38 +1,55 @@
 use std::cell::RefCell;
 use std::cell::Ref;
 use std::rc::Rc;
-use std::ops::{Add, Sub, Index, IndexMut};
+use std::ops::{Add, Div, Mul, Sub, Index, IndexMut, Range};
 use std::fmt;
 use std::fmt::Debug;
 
-use super::{ Operation, SupportedDataTypes, Shape, Tensor, TensorBinding, TensorError, TensorOperationResult, TwoValueOperation};
+use super::{ Operation, SupportedDataTypes, Shape, Tensor, TensorError, TwoValueOperation};
+
+/// Changes a tensor remembers the changed elements of.
+const CHANGE_LOG: usize = 16;
 
 impl<T> Tensor<T>
 where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
     pub fn new(vec: Vec<T>) -> Tensor<T> {
         let shape = vec![vec.len(); 1];
-        Tensor {value: RefCell::new(vec), change: Rc::new(RefCell::new(0)), shape: RefCell::new(shape), is_const: false}
+        Tensor {value: Rc::new(RefCell::new(vec)), change: Rc::new(RefCell::new(0)), changed: Rc::new(RefCell::new(Vec::new())), shape: RefCell::new(shape), is_const: false}
     }
 
     pub fn with_shape(vec: Vec<T>, shape: Shape) -> Tensor<T> {
-        Tensor {value: RefCell::new(vec), change: Rc::new(RefCell::new(0)), shape: RefCell::new(shape), is_const: false}
+        Tensor {value: Rc::new(RefCell::new(vec)), change: Rc::new(RefCell::new(0)), changed: Rc::new(RefCell::new(Vec::new())), shape: RefCell::new(shape), is_const: false}
+    }
+
+    /// A tensor that can't be changed after creation and may be folded into the generated kernel.
+    pub fn constant(vec: Vec<T>) -> Tensor<T> {
+        let shape = vec![vec.len(); 1];
+        Tensor {value: Rc::new(RefCell::new(vec)), change: Rc::new(RefCell::new(0)), changed: Rc::new(RefCell::new(Vec::new())), shape: RefCell::new(shape), is_const: true}
+    }
+
+    pub fn constant_with_shape(vec: Vec<T>, shape: Shape) -> Tensor<T> {
+        Tensor {value: Rc::new(RefCell::new(vec)), change: Rc::new(RefCell::new(0)), changed: Rc::new(RefCell::new(Vec::new())), shape: RefCell::new(shape), is_const: true}
     }
 
     pub fn from_shape_and_value(value: T, shape: Vec<usize>) -> Tensor<T> {
-        let vec = vec![value; shape.iter().sum()];
-        Tensor {value: RefCell::new(vec), change: Rc::new(RefCell::new(0)), shape: RefCell::new(shape), is_const: false}
+        let vec = vec![value; shape.iter().product()];
+        Tensor {value: Rc::new(RefCell::new(vec)), change: Rc::new(RefCell::new(0)), changed: Rc::new(RefCell::new(Vec::new())), shape: RefCell::new(shape), is_const: false}
     }
 
     pub fn zeros_from_shape(shape: Vec<usize>) -> Tensor<T> {
         Self::from_shape_and_value(T::get_zero(), shape)
     }
 
+    pub fn is_const(&self) -> bool {
+        self.is_const
+    }
+
     pub fn is_single(&self) -> bool {
         self.shape_len() == 1
     }
 
     pub fn shape_len(&self) -> usize {
-        self.shape.borrow().iter().sum()
+        self.shape.borrow().iter().product()
     }
 
     pub fn get_value(&self) -> Ref<Vec<T>> {
@@ -64,6 +81,7 @@ where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
     }
 
     pub fn set(&self, index: &[usize], val: T) {
+        if self.is_const {panic!("Tensor is defined as constant and therefor not changeable")}
         let shape = &*self.get_shape();
         if shape.len() != index.len() {panic!("Tensor: Index out of Bounds")}
 
@@ -83,8 +101,8 @@ where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
 
         if sum > self.shape_len() {panic!("Impossible index out of Bounds???")}
 
-        *self.change.borrow_mut() += 1;
         self.value.borrow_mut()[sum] = val;
+        self.record_change(sum..sum + 1);
     }
 
     pub fn get_shape(&self) -> Ref<Shape> {
@@ -106,7 +124,7 @@ where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
         let mut old_val = self.value.borrow_mut();
         old_val.clear();
         old_val.append(&mut val);
-        *self.change.borrow_mut() += 1;
+        self.record_change(0..target_length);
 
         Ok(())
     }
@@ -115,10 +133,40 @@ where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
         *self.change.borrow()
     }
 
+    fn record_change(&self, range: Range<usize>) {
+        *self.change.borrow_mut() += 1;
+        let mut changed = self.changed.borrow_mut();
+        if changed.len() == CHANGE_LOG {
+            changed.remove(0);
+        }
+        changed.push(range);
+    }
+
+    /// Elements changed after change number `since`, all of them if more changes happened than
+    /// are remembered.
+    pub(crate) fn get_changed_range(&self, since: u32) -> Range<usize> {
+        let count = (self.get_change() - since) as usize;
+        let changed = self.changed.borrow();
+        if count > changed.len() {
+            return 0..self.shape_len()
+        }
+        changed[changed.len() - count..].iter()
+            .fold(None, |all: Option<Range<usize>>, x| Some(match all {
+                Some(all) => all.start.min(x.start)..all.end.max(x.end),
+                None => x.clone()
+            }))
+            .unwrap_or(0..0)
+    }
+
     pub(crate) fn copy(&self) -> Tensor<T> {
         Tensor::with_shape(self.get_value().to_vec(), self.get_shape().to_vec())
     }
 
+    /// A second handle to the same storage, so changes through either one are seen by both.
+    pub(crate) fn share(&self) -> Tensor<T> {
+        Tensor {value: self.value.clone(), change: self.change.clone(), changed: self.changed.clone(), shape: RefCell::new(self.get_shape().to_vec()), is_const: self.is_const}
+    }
+
     pub(crate) fn same_shape_as<U>(&self, other: &Tensor<U>) -> bool 
     where U: SupportedDataTypes + SupportedDataTypes<BindingType = U> {
         let s1 = &*self.get_shape();
@@ -143,37 +191,19 @@ where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
 impl<'a, T, U> Add<&'a Tensor<U>> for &'a Tensor<T>
 where T: SupportedDataTypes + SupportedDataTypes<BindingType = T>,
       U: SupportedDataTypes + SupportedDataTypes<BindingType = U> {
-    type Output = Operation<'a>;
-
-    fn add(self, other: &'a Tensor<U>) -> Operation<'a> {
-        Operation::DualOp {
-            left: Box::new(Operation::Var(Box::new(TensorBinding::from_tensor(self, 0u32)))), 
-            right: Box::new(Operation::Var(Box::new(TensorBinding::from_tensor(other, 1u32)))),
-            result: TensorOperationResult::from_2(self, other, TwoValueOperation::Add),
-            op: TwoValueOperation::Add
-        }
+    type Output = Operation;
+
+    fn add(self, other: &'a Tensor<U>) -> Operation {
+        Operation::dual(Operation::from(self), Operation::from(other), TwoValueOperation::Add)
     }
 }
 
-impl<'a, T> Add<Operation<'a>> for &'a Tensor<T>
+impl<'a, T> Add<Operation> for &'a Tensor<T>
 where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
-    type Output = Operation<'a>;
-
-    fn add(self, op: Operation<'a>) -> Operation<'a> {
-        let result = TensorOperationResult::from_1_and_op(self, &op, TwoValueOperation::Add);
-        let binding = op.get_last_binding() + 1;
-
-        let tensor = match op.contains_tensor(self) {
-            Some(x) => x.copy(),
-            None => TensorBinding::from_tensor(self, binding)
-        };
-
-        Operation::DualOp {
-            left: Box::new(Operation::Var(Box::new(tensor))), 
-            right: Box::new(op),
-            result,
-            op: TwoValueOperation::Add
-        }
+    type Output = Operation;
+
+    fn add(self, op: Operation) -> Operation {
+        Operation::dual(Operation::from(self), op, TwoValueOperation::Add)
     }
 }
 
@@ -181,37 +211,59 @@ where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
 impl<'a, T, U> Sub<&'a Tensor<U>> for &'a Tensor<T>
 where T: SupportedDataTypes + SupportedDataTypes<BindingType = T>,
       U: SupportedDataTypes + SupportedDataTypes<BindingType = U> {
-    type Output = Operation<'a>;
-
-    fn sub(self, other: &'a Tensor<U>) -> Operation<'a> {
-        Operation::DualOp {
-            left: Box::new(Operation::Var(Box::new(TensorBinding::from_tensor(self, 0u32)))), 
-            right: Box::new(Operation::Var(Box::new(TensorBinding::from_tensor(other, 1u32)))),
-            result: TensorOperationResult::from_2(self, other, TwoValueOperation::Subtract),
-            op: TwoValueOperation::Subtract
-        }
+    type Output = Operation;
+
+    fn sub(self, other: &'a Tensor<U>) -> Operation {
+        Operation::dual(Operation::from(self), Operation::from(other), TwoValueOperation::Subtract)
     }
 }
 
-impl<'a, T> Sub<Operation<'a>> for &'a Tensor<T>
+impl<'a, T> Sub<Operation> for &'a Tensor<T>
 where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
-    type Output = Operation<'a>;
-
-    fn sub(self, op: Operation<'a>) -> Operation<'a> {
-        let result = TensorOperationResult::from_1_and_op(self, &op, TwoValueOperation::Subtract);
-        let binding = op.get_last_binding() + 1;
-
-        let tensor = match op.contains_tensor(self) {
-            Some(x) => x.copy(),
-            None => TensorBinding::from_tensor(self, binding)
-        };
-
-        Operation::DualOp {
-            left: Box::new(Operation::Var(Box::new(tensor))), 
-            right: Box::new(op),
-            result,
-            op: TwoValueOperation::Subtract
-        }
+    type Output = Operation;
+
+    fn sub(self, op: Operation) -> Operation {
+        Operation::dual(Operation::from(self), op, TwoValueOperation::Subtract)
+    }
+}
+
+
+impl<'a, T, U> Mul<&'a Tensor<U>> for &'a Tensor<T>
+where T: SupportedDataTypes + SupportedDataTypes<BindingType = T>,
+      U: SupportedDataTypes + SupportedDataTypes<BindingType = U> {
+    type Output = Operation;
+
+    fn mul(self, other: &'a Tensor<U>) -> Operation {
+        Operation::dual(Operation::from(self), Operation::from(other), TwoValueOperation::Multiply)
+    }
+}
+
+impl<'a, T> Mul<Operation> for &'a Tensor<T>
+where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+    type Output = Operation;
+
+    fn mul(self, op: Operation) -> Operation {
+        Operation::dual(Operation::from(self), op, TwoValueOperation::Multiply)
+    }
+}
+
+
+impl<'a, T, U> Div<&'a Tensor<U>> for &'a Tensor<T>
+where T: SupportedDataTypes + SupportedDataTypes<BindingType = T>,
+      U: SupportedDataTypes + SupportedDataTypes<BindingType = U> {
+    type Output = Operation;
+
+    fn div(self, other: &'a Tensor<U>) -> Operation {
+        Operation::dual(Operation::from(self), Operation::from(other), TwoValueOperation::Divide)
+    }
+}
+
+impl<'a, T> Div<Operation> for &'a Tensor<T>
+where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+    type Output = Operation;
+
+    fn div(self, op: Operation) -> Operation {
+        Operation::dual(Operation::from(self), op, TwoValueOperation::Divide)
     }
 }
 
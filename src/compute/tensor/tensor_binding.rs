@@ -1,12 +1,23 @@
 use std::cell::RefCell;
+use std::ops::Range;
 use std::rc::Rc;
 
-use super::{SupportedDataTypes, Tensor, TensorBinding, INPUT_NAME};
+use super::{BindingInfo, DataType, Shape, SupportedDataTypes, Tensor, TensorBinding, TensorError, TensorHolder, INPUT_NAME};
 
-impl<'a> TensorBinding<'a> {
-    pub(crate) fn from_tensor<T>(tensor: &'a Tensor<T>, id: u32) -> Self
+impl TensorBinding {
+    /// Binding ids are only assigned when an operation is lowered to a `Graph`.
+    pub(crate) fn from_tensor<T>(tensor: &Tensor<T>) -> Self
     where T: SupportedDataTypes + SupportedDataTypes<BindingType = T>, {
-        TensorBinding {id, value: T::to_data_holder(tensor), change: Rc::new(RefCell::new(tensor.get_change()))}
+        TensorBinding {id: 0, name: None, value: T::to_data_holder(tensor), change: Rc::new(RefCell::new(tensor.get_change()))}
+    }
+
+    pub(crate) fn from_holder(value: TensorHolder) -> Self {
+        let change = Rc::new(RefCell::new(value.get_change()));
+        TensorBinding {id: 0, name: None, value, change}
+    }
+
+    pub(crate) fn named(value: TensorHolder, name: &str) -> Self {
+        TensorBinding {name: Some(name.to_string()), ..TensorBinding::from_holder(value)}
     }
 
     pub(crate) fn has_changed(&self) -> bool {
@@ -17,15 +28,67 @@ impl<'a> TensorBinding<'a> {
         false
     }
 
+    /// Treats the current elements as uploaded, so that only later changes count.
+    pub(crate) fn mark_seen(&self) {
+        *self.change.borrow_mut() = self.value.get_change();
+    }
+
+    /// Elements changed since the last call or `has_changed`, `None` if there were no changes.
+    pub(crate) fn take_changed_range(&self) -> Option<Range<usize>> {
+        let since = *self.change.borrow();
+        if !self.has_changed() {
+            return None
+        }
+        Some(self.value.get_changed_range(since))
+    }
+
     pub(crate) fn get_type_glsl(&self) -> String {
         self.value.get_type_glsl()
     }
 
+    pub(crate) fn get_type_wgsl(&self) -> Result<String, TensorError> {
+        self.value.get_type_wgsl()
+    }
+
     pub(crate) fn get_value_glsl(&self, index: &str) -> String {
         if self.value.is_single() {format!("{}{}", INPUT_NAME, self.id)} else {format!("{}{}[{}]", INPUT_NAME, self.id, index)}
     }
 
     pub(crate) fn copy(&self) -> Self {
-        TensorBinding {id: self.id, value: self.value.copy(), change: Rc::new(RefCell::new(*self.change.borrow()))}
+        TensorBinding {id: self.id, name: self.name.clone(), value: self.value.copy(), change: Rc::new(RefCell::new(*self.change.borrow()))}
+    }
+}
+impl BindingInfo {
+    pub fn get_binding(&self) -> u32 {
+        self.binding
+    }
+
+    /// Name of the `Input` bound here, if any.
+    pub fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn is_result(&self) -> bool {
+        self.is_result
+    }
+
+    pub fn get_data_type(&self) -> DataType {
+        self.data_type
+    }
+
+    pub fn get_shape(&self) -> &Shape {
+        &self.shape
+    }
+}
+
+impl DataType {
+    pub(crate) fn from_strength(strength: usize) -> DataType {
+        match strength {
+            0 => DataType::Int,
+            1 => DataType::UInt,
+            2 => DataType::Float,
+            3 => DataType::Double,
+            _ => unreachable!("no data type has strength {}", strength)
+        }
     }
-}
\ No newline at end of file
+}
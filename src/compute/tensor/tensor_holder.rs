@@ -1,11 +1,12 @@
-use std::ptr;
+use std::ops::Range;
+use std::rc::Rc;
 use wgpu;
 use std::convert::TryInto;
 use wgpu::BufferView;
 
-use super::{TensorHolder};
+use super::{Shape, SupportedDataTypes, Tensor, TensorError, TensorHolder};
 
-impl<'a> TensorHolder<'a> {
+impl TensorHolder {
     pub(crate) fn get_change(&self) -> u32 {
         match self {
             TensorHolder::Int(x) => x.get_change(),
@@ -15,7 +16,7 @@ impl<'a> TensorHolder<'a> {
         }
     }
 
-    pub(crate) fn map_from_staging_buffer(&self, data: BufferView<'a>) {
+    pub(crate) fn map_from_staging_buffer(&self, data: BufferView<'_>) {
         match self {
             TensorHolder::Int(x) => 
                 x.change_value(data
@@ -37,7 +38,7 @@ impl<'a> TensorHolder<'a> {
 
             TensorHolder::Double(x) => 
                 x.change_value(data
-                    .chunks_exact(4)
+                    .chunks_exact(8)
                     .map(|b| f64::from_ne_bytes(b.try_into().unwrap()))
                     .collect())
         };
@@ -52,6 +53,35 @@ impl<'a> TensorHolder<'a> {
         }) as wgpu::BufferAddress
     }
 
+    /// The values as they are laid out in a buffer.
+    pub(crate) fn get_bytes(&self) -> Vec<u8> {
+        self.get_range_bytes(0..self.get_shape().iter().product())
+    }
+
+    /// The elements in `range` as they are laid out in a buffer.
+    pub(crate) fn get_range_bytes(&self, range: Range<usize>) -> Vec<u8> {
+        match self {
+            TensorHolder::Int(x) => bytemuck::cast_slice(&x.get_value()[range]).to_vec(),
+            TensorHolder::UInt(x) => bytemuck::cast_slice(&x.get_value()[range]).to_vec(),
+            TensorHolder::Float(x) => bytemuck::cast_slice(&x.get_value()[range]).to_vec(),
+            TensorHolder::Double(x) => bytemuck::cast_slice(&x.get_value()[range]).to_vec()
+        }
+    }
+
+    pub(crate) fn get_changed_range(&self, since: u32) -> Range<usize> {
+        match self {
+            TensorHolder::Int(x) => x.get_changed_range(since),
+            TensorHolder::UInt(x) => x.get_changed_range(since),
+            TensorHolder::Float(x) => x.get_changed_range(since),
+            TensorHolder::Double(x) => x.get_changed_range(since)
+        }
+    }
+
+    /// Bytes of one element.
+    pub(crate) fn get_element_size(&self) -> u64 {
+        if self.get_strength() == 3 {8} else {4}
+    }
+
     pub(crate) fn is_single(&self) -> bool {
         match self {
             TensorHolder::Int(x) => x.is_single(),
@@ -61,6 +91,75 @@ impl<'a> TensorHolder<'a> {
         }
     }
 
+    pub(crate) fn is_const(&self) -> bool {
+        match self {
+            TensorHolder::Int(x) => x.is_const,
+            TensorHolder::UInt(x) => x.is_const,
+            TensorHolder::Float(x) => x.is_const,
+            TensorHolder::Double(x) => x.is_const
+        }
+    }
+
+    pub(crate) fn get_strength(&self) -> usize {
+        match self {
+            TensorHolder::Int(_) => 0,
+            TensorHolder::UInt(_) => 1,
+            TensorHolder::Float(_) => 2,
+            TensorHolder::Double(_) => 3
+        }
+    }
+
+    pub(crate) fn get_shape(&self) -> Shape {
+        match self {
+            TensorHolder::Int(x) => x.get_shape().to_vec(),
+            TensorHolder::UInt(x) => x.get_shape().to_vec(),
+            TensorHolder::Float(x) => x.get_shape().to_vec(),
+            TensorHolder::Double(x) => x.get_shape().to_vec()
+        }
+    }
+
+    pub(crate) fn get_f64(&self, index: usize) -> f64 {
+        match self {
+            TensorHolder::Int(x) => { let v = x.get_value(); (if x.is_single() {v[0]} else {v[index]}) as f64 },
+            TensorHolder::UInt(x) => { let v = x.get_value(); (if x.is_single() {v[0]} else {v[index]}) as f64 },
+            TensorHolder::Float(x) => { let v = x.get_value(); (if x.is_single() {v[0]} else {v[index]}) as f64 },
+            TensorHolder::Double(x) => { let v = x.get_value(); if x.is_single() {v[0]} else {v[index]} }
+        }
+    }
+
+    /// Replaces the values of this tensor with those of `other`, which needs the same type and shape.
+    pub(crate) fn assign_from(&self, other: &TensorHolder) -> Result<(), TensorError> {
+        if self.get_shape() != other.get_shape() {
+            return Err(TensorError::ShapeError(format!("Expected shape {:?}, but got {:?}", self.get_shape(), other.get_shape())))
+        }
+
+        match (self, other) {
+            (TensorHolder::Int(x), TensorHolder::Int(y)) => x.change_value(y.get_value().to_vec()),
+            (TensorHolder::UInt(x), TensorHolder::UInt(y)) => x.change_value(y.get_value().to_vec()),
+            (TensorHolder::Float(x), TensorHolder::Float(y)) => x.change_value(y.get_value().to_vec()),
+            (TensorHolder::Double(x), TensorHolder::Double(y)) => x.change_value(y.get_value().to_vec()),
+            _ => Err(TensorError::TypeError(format!("Expected {}, but got {}", self.get_type_name(), other.get_type_name())))
+        }
+    }
+
+    pub(crate) fn get_type_name(&self) -> String {
+        match self {
+            TensorHolder::Int(_) => "i32",
+            TensorHolder::UInt(_) => "u32",
+            TensorHolder::Float(_) => "f32",
+            TensorHolder::Double(_) => "f64",
+        }.to_string()
+    }
+
+    pub(crate) fn get_element_type_glsl(&self) -> String {
+        match self {
+            TensorHolder::Int(_) => "int",
+            TensorHolder::UInt(_) => "uint",
+            TensorHolder::Float(_) => "float",
+            TensorHolder::Double(_) => "double",
+        }.to_string()
+    }
+
     pub(crate) fn get_type_glsl(&self) -> String {
         match self {
             TensorHolder::Int(x) => if x.is_single() {"int"} else {"int[]"},
@@ -70,43 +169,65 @@ impl<'a> TensorHolder<'a> {
         }.to_string()
     }
 
+    pub(crate) fn get_element_type_wgsl(&self) -> Result<String, TensorError> {
+        match self {
+            TensorHolder::Int(_) => Ok("i32".to_string()),
+            TensorHolder::UInt(_) => Ok("u32".to_string()),
+            TensorHolder::Float(_) => Ok("f32".to_string()),
+            TensorHolder::Double(_) => Err(TensorError::Unimplemented("WGSL kernels don't support f64".to_string()))
+        }
+    }
+
+    pub(crate) fn get_type_wgsl(&self) -> Result<String, TensorError> {
+        let element = self.get_element_type_wgsl()?;
+        Ok(if self.is_single() {element} else {format!("array<{}>", element)})
+    }
+
     pub(crate) fn copy(&self) -> Self {
         match self {
-            TensorHolder::Int(x) => TensorHolder::Int(x),
-            TensorHolder::UInt(x) => TensorHolder::UInt(x),
-            TensorHolder::Float(x) => TensorHolder::Float(x),
-            TensorHolder::Double(x) => TensorHolder::Double(x),
+            TensorHolder::Int(x) => TensorHolder::Int(x.share()),
+            TensorHolder::UInt(x) => TensorHolder::UInt(x.share()),
+            TensorHolder::Float(x) => TensorHolder::Float(x.share()),
+            TensorHolder::Double(x) => TensorHolder::Double(x.share()),
         }
     }
 }
 
-impl<'a> PartialEq for TensorHolder<'a> {
+impl PartialEq for TensorHolder {
     fn eq(&self, other: &Self) -> bool {
         match self {
             TensorHolder::Int(x) => {
                 match other {
-                    TensorHolder::Int(y) => ptr::eq(*x, *y),
+                    TensorHolder::Int(y) => Rc::ptr_eq(&x.value, &y.value),
                     _ => false
                 }
             }
             TensorHolder::UInt(x) => {
                 match other {
-                    TensorHolder::UInt(y) => ptr::eq(*x, *y),
+                    TensorHolder::UInt(y) => Rc::ptr_eq(&x.value, &y.value),
                     _ => false
                 }
             }
             TensorHolder::Float(x) => {
                 match other {
-                    TensorHolder::Float(y) => ptr::eq(*x, *y),
+                    TensorHolder::Float(y) => Rc::ptr_eq(&x.value, &y.value),
                     _ => false
                 }
             }
             TensorHolder::Double(x) => {
                 match other {
-                    TensorHolder::Double(y) => ptr::eq(*x, *y),
+                    TensorHolder::Double(y) => Rc::ptr_eq(&x.value, &y.value),
                     _ => false
                 }
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// Shares the values of `tensor`, like `SupportedDataTypes::to_data_holder`.
+impl<'a, T> From<&'a Tensor<T>> for TensorHolder
+where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
+    fn from(tensor: &'a Tensor<T>) -> TensorHolder {
+        T::to_data_holder(tensor)
+    }
+}
@@ -9,139 +9,74 @@ use std::convert::TryInto;
 use wgpu;
 use wgpu::BufferView;
 
-use super::{Operation, SupportedDataTypes, Shape, Tensor, TensorError, TensorOperationResult, TwoValueOperation};
+use super::{Operation, SingleValueOperation, SupportedDataTypes, Shape, Tensor, TensorError, TensorHolder, TensorOperationResult, TwoValueOperation};
 
 
 impl TensorOperationResult {
-    pub(crate) fn from_2<T, U>(t1: &Tensor<T>, t2: &Tensor<U>, op: TwoValueOperation) -> Self
-    where T: SupportedDataTypes + SupportedDataTypes<BindingType = T>,
-          U: SupportedDataTypes + SupportedDataTypes<BindingType = U> {
-
-        let shape1 = &*t1.get_shape();
-        let shape2 = &*t2.get_shape();
-        
-        let result_shape = TensorOperationResult::get_result_shape(shape1, shape2, &op);
-
-        if let Err(x) = result_shape {
-            return TensorOperationResult::Error(x)
+    pub(crate) fn from_parts(shape1: &Shape, strength1: usize, shape2: &Shape, strength2: usize, op: TwoValueOperation) -> Self {
+        match TensorOperationResult::get_result_shape(shape1, shape2, &op) {
+            Ok(shape) => TensorOperationResult::with_strength(shape, cmp::max(strength1, strength2)),
+            Err(x) => TensorOperationResult::Error(x)
         }
+    }
 
-        let shape = result_shape.unwrap();
-        let m = cmp::max(T::strength(), U::strength());
-
-        match m {
-            0 => TensorOperationResult::Int(Box::new(Tensor::with_shape(vec![0i32; shape.iter().sum()], shape))),
-            1 => TensorOperationResult::UInt(Box::new(Tensor::with_shape(vec![0u32; shape.iter().sum()], shape))),
-            2 => TensorOperationResult::Float(Box::new(Tensor::with_shape(vec![0f32; shape.iter().sum()], shape))),
-            3 => TensorOperationResult::Double(Box::new(Tensor::with_shape(vec![0f64; shape.iter().sum()], shape))),
+    pub(crate) fn with_strength(shape: Shape, strength: usize) -> Self {
+        match strength {
+            0 => TensorOperationResult::Int(Box::new(Tensor::with_shape(vec![0i32; shape.iter().product()], shape))),
+            1 => TensorOperationResult::UInt(Box::new(Tensor::with_shape(vec![0u32; shape.iter().product()], shape))),
+            2 => TensorOperationResult::Float(Box::new(Tensor::with_shape(vec![0f32; shape.iter().product()], shape))),
+            3 => TensorOperationResult::Double(Box::new(Tensor::with_shape(vec![0f64; shape.iter().product()], shape))),
             _ => TensorOperationResult::Error(TensorError::Unimplemented("Don't know type".to_string()))
         }
     }
 
     pub(crate) fn from_2_ops(operation1: &Operation, operation2: &Operation, op: TwoValueOperation) -> Self {
-        let shape1 =  match operation2 {
-            Operation::DualOp {result, ..} => {
-                result.get_own_shape()
-            }
-            _ => Shape::new()
-        };
-
-        let shape2 = match operation2 {
-            Operation::DualOp {result, ..} => {
-                result.get_own_shape()
-            }
-            _ => Shape::new()
-        };
-        
-        let result_shape = TensorOperationResult::get_result_shape(&shape1, &shape2, &op);
-
-        if let Err(x) = result_shape {
+        if let Some(x) = operation1.get_error().or_else(|| operation2.get_error()) {
             return TensorOperationResult::Error(x)
         }
-
-        let op1_strength = match operation1 {
-            Operation::DualOp {result, ..} => {
-                result.get_own_strength()
-            }
-            _ => usize::MAX
-        };
-
-        let op2_strength = match operation2 {
-            Operation::DualOp {result, ..} => {
-                result.get_own_strength()
-            }
-            _ => usize::MAX
-        };
-
-        let shape = result_shape.unwrap();
-        let m = cmp::max(op1_strength, op2_strength);
-
-        match m {
-            0 => TensorOperationResult::Int(Box::new(Tensor::with_shape(vec![0i32; shape.iter().sum()], shape))),
-            1 => TensorOperationResult::UInt(Box::new(Tensor::with_shape(vec![0u32; shape.iter().sum()], shape))),
-            2 => TensorOperationResult::Float(Box::new(Tensor::with_shape(vec![0f32; shape.iter().sum()], shape))),
-            3 => TensorOperationResult::Double(Box::new(Tensor::with_shape(vec![0f64; shape.iter().sum()], shape))),
-            _ => TensorOperationResult::Error(TensorError::Unimplemented("Don't know type".to_string()))
-        }
+        TensorOperationResult::from_parts(&operation1.get_shape(), operation1.get_strength(), &operation2.get_shape(), operation2.get_strength(), op)
     }
 
     pub(crate) fn from_1_and_op<T>(t1: &Tensor<T>, operation: &Operation, op: TwoValueOperation) -> Self
     where T: SupportedDataTypes + SupportedDataTypes<BindingType = T> {
-
-        let shape1 = &*t1.get_shape();
-        let shape2 = match operation {
-            Operation::DualOp {result, ..} => {
-                result.get_own_shape()
-            }
-            _ => Shape::new()
-        };
-        
-        let result_shape = TensorOperationResult::get_result_shape(shape1, &shape2, &op);
-
-        if let Err(x) = result_shape {
+        if let Some(x) = operation.get_error() {
             return TensorOperationResult::Error(x)
         }
+        TensorOperationResult::from_parts(&*t1.get_shape(), T::strength(), &operation.get_shape(), operation.get_strength(), op)
+    }
 
-        let op_strength = match operation {
-            Operation::DualOp {result, ..} => {
-                result.get_own_strength()
-            }
-            _ => usize::MAX
-        };
-
-        let shape = result_shape.unwrap();
-        let m = cmp::max(T::strength(), op_strength);
-
-        match m {
-            0 => TensorOperationResult::Int(Box::new(Tensor::with_shape(vec![0i32; shape.iter().sum()], shape))),
-            1 => TensorOperationResult::UInt(Box::new(Tensor::with_shape(vec![0u32; shape.iter().sum()], shape))),
-            2 => TensorOperationResult::Float(Box::new(Tensor::with_shape(vec![0f32; shape.iter().sum()], shape))),
-            3 => TensorOperationResult::Double(Box::new(Tensor::with_shape(vec![0f64; shape.iter().sum()], shape))),
-            _ => TensorOperationResult::Error(TensorError::Unimplemented("Don't know type".to_string()))
+    pub(crate) fn from_single(shape: Shape, strength: usize, op: SingleValueOperation) -> Self {
+        match op {
+            SingleValueOperation::SquareRoot => TensorOperationResult::with_strength(shape, strength),
+            SingleValueOperation::Sum => TensorOperationResult::with_strength(vec![1], strength),
+            SingleValueOperation::Transpose if shape.len() == 2 => TensorOperationResult::with_strength(vec![shape[1], shape[0]], strength),
+            SingleValueOperation::Transpose => TensorOperationResult::Error(TensorError::ShapeError(format!("Can't transpose shape {:?}", shape)))
         }
     }
 
+    /// Elementwise operations need equal shapes, unless one side is a single value.
     fn get_result_shape(s1: &Shape, s2: &Shape, op: &TwoValueOperation) -> Result<Shape, TensorError> {
-        match (op) {
-            TwoValueOperation::Add => {
-                if s1.len() == 1 && s1[0] == 1 {return Ok(s2.to_vec())}
-                if s2.len() == 1 && s2[0] == 1  {return Ok(s1.to_vec())}
-                if s1.len() != s2.len() {return Err(TensorError::ShapeError("Add shapes not matching".to_string()))}
-                if s1.iter().zip(s2).any(|(a, b)| a != b) {return Err(TensorError::ShapeError("Add shapes not matching".to_string()))}
-                Ok(s1.to_vec())
-            },
-            TwoValueOperation::Subtract => {
-                if s1.len() == 1 && s1[0] == 1 {return Ok(s2.to_vec())}
-                if s2.len() == 1 && s2[0] == 1  {return Ok(s1.to_vec())}
-                if s1.len() != s2.len() {return Err(TensorError::ShapeError("Add shapes not matching".to_string()))}
-                if s1.iter().zip(s2).any(|(a, b)| a != b) {return Err(TensorError::ShapeError("Add shapes not matching".to_string()))}
-                Ok(s1.to_vec())
-            },
-            _ =>  Err(TensorError::ShapeError("Unknown shape for unknown operation".to_string()))
-        }
-    }
-
-    fn get_own_strength(&self) -> usize {
+        if *op == TwoValueOperation::MatMul {
+            if s1.len() != 2 || s2.len() != 2 || s1[1] != s2[0] {
+                return Err(TensorError::ShapeError(format!("Matmul shapes not matching: {:?} and {:?}", s1, s2)))
+            }
+            return Ok(vec![s1[0], s2[1]])
+        }
+        if s1.len() == 1 && s1[0] == 1 {return Ok(s2.to_vec())}
+        if s2.len() == 1 && s2[0] == 1 {return Ok(s1.to_vec())}
+        if s1 != s2 {
+            let name = match op {
+                TwoValueOperation::Add | TwoValueOperation::Subtract => "Add",
+                TwoValueOperation::Multiply => "Multiply",
+                TwoValueOperation::Divide => "Divide",
+                TwoValueOperation::MatMul => "Matmul"
+            };
+            return Err(TensorError::ShapeError(format!("{} shapes not matching", name)))
+        }
+        Ok(s1.to_vec())
+    }
+
+    pub(crate) fn get_own_strength(&self) -> usize {
         match self {
             TensorOperationResult::Int(..) => 0,
             TensorOperationResult::UInt(..) => 1,
@@ -151,7 +86,7 @@ impl TensorOperationResult {
         }
     }
 
-    fn get_own_shape(&self) -> Shape {
+    pub(crate) fn get_own_shape(&self) -> Shape {
         match self {
             TensorOperationResult::Int(x) => (&x).get_shape().to_vec(),
             TensorOperationResult::UInt(x) => (&x).get_shape().to_vec(),
@@ -167,11 +102,11 @@ impl TensorOperationResult {
             TensorOperationResult::UInt(x) => TensorOperationResult::UInt(Box::new(x.copy())),
             TensorOperationResult::Float(x) => TensorOperationResult::Float(Box::new(x.copy())),
             TensorOperationResult::Double(x) => TensorOperationResult::Double(Box::new(x.copy())),
-            TensorOperationResult::Error(x) => TensorOperationResult::Error(TensorError::Unimplemented("Copy Error Unimplemented".to_string())),
+            TensorOperationResult::Error(x) => TensorOperationResult::Error(x.clone()),
         }
     }
 
-    pub(crate) fn map_from_staging_buffer<'a>(&self, data: BufferView<'a>) {
+    pub(crate) fn map_from_staging_buffer(&self, data: BufferView<'_>) {
         match self {
             TensorOperationResult::Int(x) => 
                 x.change_value(data
@@ -193,7 +128,7 @@ impl TensorOperationResult {
 
             TensorOperationResult::Double(x) => 
                 x.change_value(data
-                    .chunks_exact(4)
+                    .chunks_exact(8)
                     .map(|b| f64::from_ne_bytes(b.try_into().unwrap()))
                     .collect()),
 
@@ -201,6 +136,26 @@ impl TensorOperationResult {
         };
     }
 
+    pub(crate) fn map_from_f64(&self, data: Vec<f64>) -> Result<(), TensorError> {
+        match self {
+            TensorOperationResult::Int(x) => x.change_value(data.iter().map(|v| *v as i32).collect()),
+            TensorOperationResult::UInt(x) => x.change_value(data.iter().map(|v| *v as u32).collect()),
+            TensorOperationResult::Float(x) => x.change_value(data.iter().map(|v| *v as f32).collect()),
+            TensorOperationResult::Double(x) => x.change_value(data),
+            _ => Err(TensorError::Unimplemented("Cannot map values into an error result".to_string()))
+        }
+    }
+
+    pub(crate) fn shape_len(&self) -> usize {
+        match self {
+            TensorOperationResult::Int(x) => x.shape_len(),
+            TensorOperationResult::UInt(x) => x.shape_len(),
+            TensorOperationResult::Float(x) => x.shape_len(),
+            TensorOperationResult::Double(x) => x.shape_len(),
+            _ => 0
+        }
+    }
+
     pub(crate) fn get_mem_size(&self) -> wgpu::BufferAddress {
         (match self {
             TensorOperationResult::Int(x) => x.shape_len() * std::mem::size_of::<i32>(),            
@@ -211,6 +166,17 @@ impl TensorOperationResult {
         }) as wgpu::BufferAddress
     }
 
+    /// The values as they are laid out in a buffer.
+    pub(crate) fn get_bytes(&self) -> Result<Vec<u8>, TensorError> {
+        match self {
+            TensorOperationResult::Int(x) => Ok(bytemuck::cast_slice(&x.get_value()).to_vec()),
+            TensorOperationResult::UInt(x) => Ok(bytemuck::cast_slice(&x.get_value()).to_vec()),
+            TensorOperationResult::Float(x) => Ok(bytemuck::cast_slice(&x.get_value()).to_vec()),
+            TensorOperationResult::Double(x) => Ok(bytemuck::cast_slice(&x.get_value()).to_vec()),
+            TensorOperationResult::Error(x) => Err(x.clone())
+        }
+    }
+
     pub(crate) fn is_single(&self) -> bool {
         match self {
             TensorOperationResult::Int(x) => x.is_single(),
@@ -221,6 +187,78 @@ impl TensorOperationResult {
         }
     }
 
+    pub(crate) fn get_type_name(&self) -> String {
+        match self {
+            TensorOperationResult::Int(_) => "i32",
+            TensorOperationResult::UInt(_) => "u32",
+            TensorOperationResult::Float(_) => "f32",
+            TensorOperationResult::Double(_) => "f64",
+            TensorOperationResult::Error(_) => "error"
+        }.to_string()
+    }
+
+    /// Rounds `value` the way storing it in an element of this result would.
+    pub(crate) fn cast_f64(&self, value: f64) -> f64 {
+        match self {
+            TensorOperationResult::Int(_) => (value as i32) as f64,
+            TensorOperationResult::UInt(_) => (value as u32) as f64,
+            TensorOperationResult::Float(_) => (value as f32) as f64,
+            _ => value
+        }
+    }
+
+    /// A constant tensor of this type and shape with `values`, converted from `f64`.
+    pub(crate) fn constant_from_f64(&self, values: &[f64]) -> Option<TensorHolder> {
+        let shape = self.get_own_shape();
+        match self {
+            TensorOperationResult::Int(_) => Some(TensorHolder::Int(Tensor::constant_with_shape(values.iter().map(|x| *x as i32).collect(), shape))),
+            TensorOperationResult::UInt(_) => Some(TensorHolder::UInt(Tensor::constant_with_shape(values.iter().map(|x| *x as u32).collect(), shape))),
+            TensorOperationResult::Float(_) => Some(TensorHolder::Float(Tensor::constant_with_shape(values.iter().map(|x| *x as f32).collect(), shape))),
+            TensorOperationResult::Double(_) => Some(TensorHolder::Double(Tensor::constant_with_shape(values.to_vec(), shape))),
+            TensorOperationResult::Error(_) => None
+        }
+    }
+
+    pub(crate) fn get_literal_glsl(&self, value: f64) -> String {
+        match self {
+            TensorOperationResult::Int(_) => format!("{}", value as i32),
+            TensorOperationResult::UInt(_) => format!("{}u", value as u32),
+            TensorOperationResult::Float(_) => format!("{:?}", value as f32),
+            TensorOperationResult::Double(_) => format!("{:?}lf", value),
+            _ => panic!("Unimplemented Error Handling")
+        }
+    }
+
+    pub(crate) fn get_literal_wgsl(&self, value: f64) -> Result<String, TensorError> {
+        match self {
+            TensorOperationResult::Int(_) => Ok(format!("{}", value as i32)),
+            TensorOperationResult::UInt(_) => Ok(format!("{}u", value as u32)),
+            TensorOperationResult::Float(_) => Ok(format!("{:?}", value as f32)),
+            TensorOperationResult::Double(_) => Err(TensorError::Unimplemented("WGSL kernels don't support f64".to_string())),
+            TensorOperationResult::Error(x) => Err(x.clone())
+        }
+    }
+
+    pub(crate) fn get_element_type_wgsl(&self) -> Result<String, TensorError> {
+        match self {
+            TensorOperationResult::Int(_) => Ok("i32".to_string()),
+            TensorOperationResult::UInt(_) => Ok("u32".to_string()),
+            TensorOperationResult::Float(_) => Ok("f32".to_string()),
+            TensorOperationResult::Double(_) => Err(TensorError::Unimplemented("WGSL kernels don't support f64".to_string())),
+            TensorOperationResult::Error(x) => Err(x.clone())
+        }
+    }
+
+    pub(crate) fn get_element_type_glsl(&self) -> String {
+        match self {
+            TensorOperationResult::Int(_) => "int",
+            TensorOperationResult::UInt(_) => "uint",
+            TensorOperationResult::Float(_) => "float",
+            TensorOperationResult::Double(_) => "double",
+            _ => panic!("Unimplemented Error Handling")
+        }.to_string()
+    }
+
     pub(crate) fn get_type_glsl(&self) -> String {
         match self {
             TensorOperationResult::Int(x) => if x.is_single() {"int"} else {"int[]"},
@@ -0,0 +1,71 @@
+use super::{Graph, MemoryPlan, TensorBinding, UniformLayout};
+
+/// Bytes of the `uvec4` every kernel has in front of the scalars.
+const DIMS_SIZE: u64 = 16;
+
+impl Graph {
+    /// Where the kernels of `plan` find their dimensions and the scalar inputs, which are
+    /// packed at their natural alignment like std140 does for scalars.
+    pub fn plan_uniforms(&self, plan: &MemoryPlan, workgroup_size: u32) -> UniformLayout {
+        let dims: Vec<[u32; 4]> = (0..plan.get_stage_count()).map(|stage| {
+            let invocations = self.get_stage_invocations(plan, stage);
+            let (row, layer) = Self::get_index_strides(invocations, workgroup_size);
+            [invocations as u32, row, layer, self.get_stage_length(plan, stage) as u32]
+        }).collect();
+
+        let (scalars, size) = self.pack_scalars(dims.len());
+        UniformLayout {binding: self.pool_binding(plan.get_pool().len()), dims, scalars, size}
+    }
+
+    /// Offsets of the scalar inputs after the dimensions of `stage_count` kernels, and the
+    /// size of the buffer.
+    pub(crate) fn pack_scalars(&self, stage_count: usize) -> (Vec<(u32, u64)>, u64) {
+        let mut size = stage_count as u64 * DIMS_SIZE;
+        let mut scalars = Vec::new();
+        for i in self.inputs.iter().filter(|x| x.value.is_single()) {
+            let alignment = i.value.get_mem_size();
+            size = size.div_ceil(alignment) * alignment;
+            scalars.push((i.id, size));
+            size += alignment;
+        }
+
+        // uniform buffers are bound in multiples of a vec4
+        (scalars, size.div_ceil(DIMS_SIZE) * DIMS_SIZE)
+    }
+}
+
+impl UniformLayout {
+    pub fn get_binding(&self) -> u32 {
+        self.binding
+    }
+
+    /// Size of the buffer in bytes.
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    /// Offset in bytes of the scalar input bound at `binding`, `None` for arrays.
+    pub fn get_offset(&self, binding: u32) -> Option<u64> {
+        self.scalars.iter().find(|(x, _)| *x == binding).map(|(_, offset)| *offset)
+    }
+
+    /// Index of the member holding the scalar input bound at `binding`, after the dimensions.
+    #[cfg(feature = "spirv")]
+    pub(crate) fn get_member(&self, binding: u32) -> Option<u32> {
+        self.scalars.iter().position(|(x, _)| *x == binding).map(|x| x as u32 + 1)
+    }
+
+    /// Initial contents of the buffer for `inputs`.
+    pub(crate) fn get_contents(&self, inputs: &[TensorBinding]) -> Vec<u8> {
+        let mut contents = vec![0u8; self.size as usize];
+        for (stage, dims) in self.dims.iter().enumerate() {
+            let start = stage * DIMS_SIZE as usize;
+            contents[start..start + DIMS_SIZE as usize].copy_from_slice(bytemuck::cast_slice(dims));
+        }
+        for (binding, offset) in &self.scalars {
+            let bytes = inputs[*binding as usize].value.get_bytes();
+            contents[*offset as usize..*offset as usize + bytes.len()].copy_from_slice(&bytes);
+        }
+        contents
+    }
+}
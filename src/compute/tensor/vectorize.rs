@@ -0,0 +1,185 @@
+use std::fmt::Write;
+
+use super::{Graph, MemoryPlan, Node, NodeId, SingleValueOperation, TensorError, INPUT_NAME};
+
+/// Elements every invocation of a vectorized kernel computes.
+pub const VECTOR_WIDTH: usize = 4;
+
+/// Bytes of a storage buffer holding `size` bytes, which is padded to a whole vector of
+/// 32-bit values.
+pub(crate) fn get_padded_size(size: u64) -> u64 {
+    let vector = (VECTOR_WIDTH * std::mem::size_of::<f32>()) as u64;
+    size.div_ceil(vector) * vector
+}
+
+impl Graph {
+    /// Elements every invocation of kernel `stage` computes. Kernels of a single elementwise
+    /// stage over 32-bit values, where every array has the same length, read and write vectors
+    /// of `VECTOR_WIDTH` of them. Buffers are padded to a whole vector, see `get_padded_size`,
+    /// so only the stores of the last invocation need to stop at the end of the results.
+    pub fn get_vector_width(&self, plan: &MemoryPlan, stage: usize) -> usize {
+        let length = self.get_stage_length(plan, stage);
+        if plan.get_stage_count() != 1 || length < VECTOR_WIDTH {
+            return 1
+        }
+
+        let elementwise = self.nodes.iter().enumerate().all(|(id, node)| {
+            let same_length = match node {
+                Node::Constant {..} => true,
+                _ if !self.is_elementwise(id) => false,
+                _ if self.is_broadcast(id) => true,
+                _ => self.get_node_shape(id).iter().product::<usize>() == length
+            };
+            same_length && self.get_node_strength(id) < 3
+        });
+        if elementwise {VECTOR_WIDTH} else {1}
+    }
+
+    /// Invocations kernel `stage` is dispatched with.
+    pub(crate) fn get_stage_invocations(&self, plan: &MemoryPlan, stage: usize) -> usize {
+        let width = self.get_vector_width(plan, stage);
+        self.get_stage_length(plan, stage).div_ceil(width)
+    }
+
+    /// Whether node `id` is a single value every element reads.
+    fn is_broadcast(&self, id: NodeId) -> bool {
+        match &self.nodes[id] {
+            Node::Input(x) => self.inputs[*x as usize].value.is_single(),
+            Node::Constant {..} => true,
+            _ => false
+        }
+    }
+
+    /// `build_glsl` for kernels of `get_vector_width` elements per invocation, with every
+    /// array bound as vectors.
+    pub(crate) fn build_glsl_vector(&self, plan: &MemoryPlan, stage: usize, workgroup_size: u32) -> String {
+        let uniforms = self.plan_uniforms(plan, workgroup_size);
+
+        let mut s = String::new();
+        writeln!(&mut s, "#version 450").unwrap();
+        writeln!(&mut s, "layout(local_size_x = {}) in;", workgroup_size).unwrap();
+        writeln!(&mut s).unwrap();
+
+        for i in self.inputs.iter().filter(|x| !x.value.is_single()) {
+            writeln!(&mut s, "readonly layout(set = 0, binding = {}) buffer b{} {{", i.id, i.id).unwrap();
+            writeln!(&mut s, "{}[] {}{};", get_vector_type_glsl(&i.value.get_element_type_glsl()), INPUT_NAME, i.id).unwrap();
+            writeln!(&mut s, "}};").unwrap();
+            writeln!(&mut s).unwrap();
+        }
+
+        for (i, binding) in self.result_bindings().iter().enumerate() {
+            writeln!(&mut s, "layout(set = 0, binding = {}) buffer b{} {{", binding, binding).unwrap();
+            writeln!(&mut s, "{}[] result{};", get_vector_type_glsl(&self.get_node_type_glsl(self.outputs[i])), i).unwrap();
+            writeln!(&mut s, "}};").unwrap();
+            writeln!(&mut s).unwrap();
+        }
+
+        self.write_parameters_glsl(&mut s, &uniforms);
+
+        writeln!(&mut s, "void main() {{").unwrap();
+        Self::write_index_glsl(&mut s, stage);
+        for (id, node) in self.nodes.iter().enumerate() {
+            let vector = get_vector_type_glsl(&self.get_node_type_glsl(id));
+            match node {
+                Node::Input(_) | Node::Constant {..} | Node::SingleOp {op: SingleValueOperation::Sum | SingleValueOperation::Transpose, ..} => {},
+                Node::SingleOp {value, op: SingleValueOperation::SquareRoot, ..} => {
+                    writeln!(&mut s, "{} t{} = sqrt({}({}));", vector, id, vector, self.get_operand_glsl(plan, stage, *value, "index")).unwrap();
+                },
+                Node::DualOp {left, right, op, ..} => {
+                    writeln!(&mut s, "{} t{} = ({}({}) {:?} {}({}));", vector, id, vector, self.get_operand_glsl(plan, stage, *left, "index"), op, vector, self.get_operand_glsl(plan, stage, *right, "index")).unwrap();
+                }
+            }
+        }
+
+        for (i, output) in self.outputs.iter().enumerate() {
+            let vector = get_vector_type_glsl(&self.get_node_type_glsl(*output));
+            writeln!(&mut s, "{} r{} = {}({});", vector, i, vector, self.get_operand_glsl(plan, stage, *output, "index")).unwrap();
+        }
+        // the last invocation may only have part of a vector left
+        writeln!(&mut s, "if (index * {}u + {}u <= size.w) {{", VECTOR_WIDTH, VECTOR_WIDTH).unwrap();
+        for i in 0..self.outputs.len() {
+            writeln!(&mut s, "result{}[index] = r{};", i, i).unwrap();
+        }
+        writeln!(&mut s, "}} else {{").unwrap();
+        writeln!(&mut s, "for (uint k = 0u; index * {}u + k < size.w; k++) {{", VECTOR_WIDTH).unwrap();
+        for i in 0..self.outputs.len() {
+            writeln!(&mut s, "result{}[index][k] = r{}[k];", i, i).unwrap();
+        }
+        writeln!(&mut s, "}}").unwrap();
+        writeln!(&mut s, "}}").unwrap();
+        writeln!(&mut s, "}}").unwrap();
+
+        s
+    }
+
+    /// `build_wgsl` for kernels of `get_vector_width` elements per invocation, like
+    /// `build_glsl_vector`.
+    pub(crate) fn build_wgsl_vector(&self, plan: &MemoryPlan, stage: usize, workgroup_size: u32) -> Result<String, TensorError> {
+        let uniforms = self.plan_uniforms(plan, workgroup_size);
+
+        let mut s = String::new();
+        self.write_parameters_wgsl(&mut s, &uniforms)?;
+
+        for i in self.inputs.iter().filter(|x| !x.value.is_single()) {
+            writeln!(&mut s, "@group(0) @binding({}) var<storage, read> {}{}: array<vec4<{}>>;", i.id, INPUT_NAME, i.id, i.value.get_element_type_wgsl()?).unwrap();
+        }
+
+        for (i, binding) in self.result_bindings().iter().enumerate() {
+            writeln!(&mut s, "@group(0) @binding({}) var<storage, read_write> result{}: array<vec4<{}>>;", binding, i, self.get_node_type_wgsl(self.outputs[i])?).unwrap();
+        }
+
+        writeln!(&mut s).unwrap();
+        writeln!(&mut s, "@compute @workgroup_size({})", workgroup_size).unwrap();
+        Self::write_main_wgsl(&mut s, stage);
+        for (id, node) in self.nodes.iter().enumerate() {
+            let element = self.get_node_type_wgsl(id)?;
+            match node {
+                Node::Input(_) | Node::Constant {..} | Node::SingleOp {op: SingleValueOperation::Sum | SingleValueOperation::Transpose, ..} => {},
+                Node::SingleOp {value, op: SingleValueOperation::SquareRoot, ..} => {
+                    writeln!(&mut s, "let t{} = sqrt({});", id, self.get_vector_operand_wgsl(plan, stage, *value, &element)?).unwrap();
+                },
+                Node::DualOp {left, right, op, ..} => {
+                    writeln!(&mut s, "let t{} = ({} {:?} {});", id, self.get_vector_operand_wgsl(plan, stage, *left, &element)?, op, self.get_vector_operand_wgsl(plan, stage, *right, &element)?).unwrap();
+                }
+            }
+        }
+
+        for (i, output) in self.outputs.iter().enumerate() {
+            writeln!(&mut s, "let r{} = {};", i, self.get_vector_operand_wgsl(plan, stage, *output, &self.get_node_type_wgsl(*output)?)?).unwrap();
+        }
+        // the last invocation may only have part of a vector left
+        writeln!(&mut s, "if (index * {}u + {}u <= size.w) {{", VECTOR_WIDTH, VECTOR_WIDTH).unwrap();
+        for i in 0..self.outputs.len() {
+            writeln!(&mut s, "result{}[index] = r{};", i, i).unwrap();
+        }
+        writeln!(&mut s, "}} else {{").unwrap();
+        writeln!(&mut s, "for (var k = 0u; index * {}u + k < size.w; k = k + 1u) {{", VECTOR_WIDTH).unwrap();
+        for i in 0..self.outputs.len() {
+            writeln!(&mut s, "result{}[index][k] = r{}[k];", i, i).unwrap();
+        }
+        writeln!(&mut s, "}}").unwrap();
+        writeln!(&mut s, "}}").unwrap();
+        writeln!(&mut s, "}}").unwrap();
+
+        Ok(s)
+    }
+
+    /// Vector of node `id` with elements of type `element`. WGSL only splats values of the
+    /// element type, so single values are converted before.
+    fn get_vector_operand_wgsl(&self, plan: &MemoryPlan, stage: usize, id: NodeId, element: &str) -> Result<String, TensorError> {
+        let operand = self.get_operand_wgsl(plan, stage, id, "index")?;
+        Ok(if self.is_broadcast(id) {
+            format!("vec4<{}>({}({}))", element, element, operand)
+        } else {
+            format!("vec4<{}>({})", element, operand)
+        })
+    }
+}
+
+fn get_vector_type_glsl(element: &str) -> String {
+    match element {
+        "int" => "ivec4",
+        "uint" => "uvec4",
+        _ => "vec4"
+    }.to_string()
+}
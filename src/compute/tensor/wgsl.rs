@@ -0,0 +1,130 @@
+use std::fmt::Write;
+
+use super::{Graph, MemoryPlan, Node, NodeId, SingleValueOperation, TensorError, TwoValueOperation, UniformLayout, INPUT_NAME};
+
+impl Graph {
+    /// WGSL compute shader for kernel `stage` of `plan`, with the same bindings as `build_glsl`.
+    /// WGSL has no implicit conversions, so every operand is cast to the type of its result.
+    /// Graphs with `f64` values can't be expressed and return an error.
+    pub fn build_wgsl(&self, plan: &MemoryPlan, stage: usize, workgroup_size: u32) -> Result<String, TensorError> {
+        if self.get_vector_width(plan, stage) > 1 {
+            return self.build_wgsl_vector(plan, stage, workgroup_size)
+        }
+
+        let inputs = &self.inputs;
+        let bindings = self.result_bindings();
+        let uniforms = self.plan_uniforms(plan, workgroup_size);
+
+        let mut s = String::new();
+        self.write_parameters_wgsl(&mut s, &uniforms)?;
+
+        for i in inputs.iter().filter(|x| !x.value.is_single()) {
+            writeln!(&mut s, "@group(0) @binding({}) var<storage, read> {}{}: {};", i.id, INPUT_NAME, i.id, i.get_type_wgsl()?).unwrap();
+        }
+
+        for (i, binding) in bindings.iter().enumerate() {
+            if plan.get_stage(self.outputs[i]) != stage {continue}
+            writeln!(&mut s, "@group(0) @binding({}) var<storage, read_write> result{}: array<{}>;", binding, i, self.get_node_type_wgsl(self.outputs[i])?).unwrap();
+        }
+
+        for id in 0..self.nodes.len() {
+            if let Some(slot) = plan.get_slot(id) {
+                if !self.is_used_in_stage(plan, id, stage) {continue}
+                writeln!(&mut s, "@group(0) @binding({}) var<storage, read_write> m{}: array<{}>;", self.pool_binding(slot), slot, self.get_node_type_wgsl(id)?).unwrap();
+            }
+        }
+
+        writeln!(&mut s).unwrap();
+        writeln!(&mut s, "@compute @workgroup_size({})", workgroup_size).unwrap();
+        Self::write_main_wgsl(&mut s, stage);
+        for (id, node) in self.nodes.iter().enumerate() {
+            if plan.get_stage(id) != stage {continue}
+            match node {
+                Node::Input(_) | Node::Constant {..} => {},
+                Node::SingleOp {value, result, op: SingleValueOperation::Sum} => {
+                    let length: usize = self.get_node_shape(*value).iter().product();
+                    let element = result.get_element_type_wgsl()?;
+                    writeln!(&mut s, "var t{}: {} = {};", id, element, result.get_literal_wgsl(0.0)?).unwrap();
+                    writeln!(&mut s, "for (var i{} = 0u; i{} < {}u; i{} = i{} + 1u) {{", id, id, length, id, id).unwrap();
+                    writeln!(&mut s, "t{} = t{} + {}({});", id, id, element, self.get_operand_wgsl(plan, stage, *value, &format!("i{}", id))?).unwrap();
+                    writeln!(&mut s, "}}").unwrap();
+                },
+                Node::SingleOp {value, result, op: SingleValueOperation::Transpose} => {
+                    let (rows, columns) = self.get_matrix_shape(id);
+                    writeln!(&mut s, "let j{} = min(index, {}u);", id, rows * columns - 1).unwrap();
+                    let element = format!("(j{} % {}u) * {}u + j{} / {}u", id, columns, rows, id, columns);
+                    writeln!(&mut s, "let t{} = {}({});", id, result.get_element_type_wgsl()?, self.get_operand_wgsl(plan, stage, *value, &element)?).unwrap();
+                },
+                Node::SingleOp {value, result, op: SingleValueOperation::SquareRoot} => {
+                    let element = result.get_element_type_wgsl()?;
+                    writeln!(&mut s, "let t{} = sqrt({}({}));", id, element, self.get_operand_wgsl(plan, stage, *value, "index")?).unwrap();
+                },
+                Node::DualOp {left, right, result, op: TwoValueOperation::MatMul} => {
+                    let (k, n) = self.get_matrix_shape(*right);
+                    let element = result.get_element_type_wgsl()?;
+                    writeln!(&mut s, "let j{} = min(index, {}u);", id, self.get_matrix_shape(id).0 * n - 1).unwrap();
+                    writeln!(&mut s, "var t{}: {} = {};", id, element, result.get_literal_wgsl(0.0)?).unwrap();
+                    writeln!(&mut s, "for (var i{} = 0u; i{} < {}u; i{} = i{} + 1u) {{", id, id, k, id, id).unwrap();
+                    let l = self.get_operand_wgsl(plan, stage, *left, &format!("j{} / {}u * {}u + i{}", id, n, k, id))?;
+                    let r = self.get_operand_wgsl(plan, stage, *right, &format!("i{} * {}u + j{} % {}u", id, n, id, n))?;
+                    writeln!(&mut s, "t{} = t{} + {}({}) * {}({});", id, id, element, l, element, r).unwrap();
+                    writeln!(&mut s, "}}").unwrap();
+                },
+                Node::DualOp {left, right, result, op} => {
+                    let element = result.get_element_type_wgsl()?;
+                    writeln!(&mut s, "let t{} = ({}({}) {:?} {}({}));", id, element, self.get_operand_wgsl(plan, stage, *left, "index")?, op, element, self.get_operand_wgsl(plan, stage, *right, "index")?).unwrap();
+                }
+            }
+
+            if let Some(slot) = plan.get_slot(id) {
+                let length: usize = self.get_node_shape(id).iter().product();
+                writeln!(&mut s, "if (index < {}u) {{ m{}[index] = t{}; }}", length, slot, id).unwrap();
+            }
+        }
+        for (i, output) in self.outputs.iter().enumerate() {
+            if plan.get_stage(*output) != stage {continue}
+            writeln!(&mut s, "if (index < arrayLength(&result{})) {{ result{}[index] = {}({}); }}", i, i, self.get_node_type_wgsl(*output)?, self.get_operand_wgsl(plan, stage, *output, "index")?).unwrap();
+        }
+        writeln!(&mut s, "}}").unwrap();
+
+        Ok(s)
+    }
+
+    /// The uniform buffer of `Graph::plan_uniforms`.
+    pub(crate) fn write_parameters_wgsl(&self, s: &mut String, uniforms: &UniformLayout) -> Result<(), TensorError> {
+        writeln!(s, "struct Parameters {{").unwrap();
+        writeln!(s, "dims: array<vec4<u32>, {}>,", uniforms.dims.len()).unwrap();
+        for i in self.inputs.iter().filter(|x| x.value.is_single()) {
+            writeln!(s, "{}{}: {},", INPUT_NAME, i.id, i.get_type_wgsl()?).unwrap();
+        }
+        writeln!(s, "}}").unwrap();
+        writeln!(s).unwrap();
+        writeln!(s, "@group(0) @binding({}) var<uniform> parameters: Parameters;", uniforms.binding).unwrap();
+        Ok(())
+    }
+
+    /// Entry point of kernel `stage` up to the index of the invocation, returning from those
+    /// past the last.
+    pub(crate) fn write_main_wgsl(s: &mut String, stage: usize) {
+        writeln!(s, "fn main(@builtin(global_invocation_id) gid: vec3<u32>) {{").unwrap();
+        writeln!(s, "let size = parameters.dims[{}];", stage).unwrap();
+        writeln!(s, "let index = gid.x + gid.y * size.y + gid.z * size.z;").unwrap();
+        writeln!(s, "if (index >= size.x) {{ return; }}").unwrap();
+    }
+
+    pub(crate) fn get_node_type_wgsl(&self, id: NodeId) -> Result<String, TensorError> {
+        match &self.nodes[id] {
+            Node::Input(x) => self.inputs[*x as usize].value.get_element_type_wgsl(),
+            Node::Constant {result, ..} | Node::SingleOp {result, ..} | Node::DualOp {result, ..} => result.get_element_type_wgsl()
+        }
+    }
+
+    /// Node `id` at element `index` as seen by kernel `stage`, like `get_operand_glsl`.
+    pub(crate) fn get_operand_wgsl(&self, plan: &MemoryPlan, stage: usize, id: NodeId, index: &str) -> Result<String, TensorError> {
+        match &self.nodes[id] {
+            Node::Input(x) if self.inputs[*x as usize].value.is_single() => Ok(format!("parameters.{}{}", INPUT_NAME, x)),
+            Node::Constant {value, result} => result.get_literal_wgsl(*value),
+            _ => Ok(self.get_operand_glsl(plan, stage, id, index))
+        }
+    }
+}
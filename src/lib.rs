@@ -1 +1,4 @@
+#[cfg(not(any(feature = "glsl", feature = "wgsl", feature = "spirv")))]
+compile_error!("one of the \"glsl\", \"wgsl\" or \"spirv\" features has to be enabled to compile kernels");
+
 pub mod compute;